@@ -0,0 +1,299 @@
+//! 構文木のモジュール。
+//! CompilationEngineが構文解析をしてClassを作り、xmlやvmコードの出力は
+//! それを読んで行う。文法は208pageに書いてある
+
+use super::span::Span;
+
+
+/// 識別子とその位置
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identifier {
+    pub name: String,
+    pub span: Span,
+}
+
+/// 'class' className '{' classVarDec* subroutineDec* '}'
+#[derive(Debug, Clone, PartialEq)]
+pub struct Class {
+    pub name: Identifier,
+    pub class_var_decs: Vec<ClassVarDec>,
+    pub subroutine_decs: Vec<SubroutineDec>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClassVarKind {
+    Static,
+    Field,
+}
+
+/// ('static' | 'field') type varName (',' varName)* ';'
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassVarDec {
+    pub kind: ClassVarKind,
+    pub ty: Type,
+    pub names: Vec<Identifier>,
+    pub span: Span,
+}
+
+/// 'int' | 'char' | 'boolean' | className
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type {
+    Int,
+    Char,
+    Boolean,
+    Class(String),
+}
+
+impl Type {
+    /// 型の名前を返す
+    pub fn name(&self) -> &str {
+        match self {
+            Type::Int => "int",
+            Type::Char => "char",
+            Type::Boolean => "boolean",
+            Type::Class(name) => name,
+        }
+    }
+
+    /// 型の名前から型を返す。int、char、boolean以外はクラス名とする
+    pub fn from_name(name: &str) -> Type {
+        match name {
+            "int" => Type::Int,
+            "char" => Type::Char,
+            "boolean" => Type::Boolean,
+            _ => Type::Class(name.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubroutineKind {
+    Constructor,
+    Function,
+    Method,
+}
+
+impl SubroutineKind {
+    /// 宣言に使うキーワードを返す
+    pub fn keyword(self) -> &'static str {
+        match self {
+            SubroutineKind::Constructor => "constructor",
+            SubroutineKind::Function => "function",
+            SubroutineKind::Method => "method",
+        }
+    }
+}
+
+/// ('constructor' | 'function' | 'method') ('void' | type) subroutineName
+/// '(' parameterList ')' subroutineBody
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubroutineDec {
+    pub kind: SubroutineKind,
+    /// voidのときはNone
+    pub return_type: Option<Type>,
+    pub name: Identifier,
+    pub parameters: Vec<Parameter>,
+    pub var_decs: Vec<VarDec>,
+    pub statements: Vec<Statement>,
+    pub span: Span,
+}
+
+/// type varName
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parameter {
+    pub ty: Type,
+    pub name: Identifier,
+    pub span: Span,
+}
+
+/// 'var' type varName (',' varName)* ';'
+#[derive(Debug, Clone, PartialEq)]
+pub struct VarDec {
+    pub ty: Type,
+    pub names: Vec<Identifier>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    /// 'let' varName ('[' expression ']')? '=' expression ';'
+    Let {
+        name: Identifier,
+        index: Option<Box<Expression>>,
+        value: Expression,
+        span: Span,
+    },
+    /// 'if' '(' expression ')' '{' statements '}'
+    /// ('else' '{' statements '}')?
+    If {
+        condition: Expression,
+        then_statements: Vec<Statement>,
+        else_statements: Option<Vec<Statement>>,
+        span: Span,
+    },
+    /// 'while' '(' expression ')' '{' statements '}'
+    While {
+        condition: Expression,
+        statements: Vec<Statement>,
+        span: Span,
+    },
+    /// 'do' subroutineCall ';'
+    Do {
+        call: SubroutineCall,
+        span: Span,
+    },
+    /// 'return' expression? ';'
+    Return {
+        value: Option<Expression>,
+        span: Span,
+    },
+}
+
+/// term (op term)*
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    pub term: Term,
+    pub rest: Vec<(BinaryOp, Term)>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    And,
+    Or,
+    Lt,
+    Gt,
+    Eq,
+}
+
+impl BinaryOp {
+    /// 演算子に対応する記号を返す
+    pub fn symbol(self) -> char {
+        match self {
+            BinaryOp::Add => '+',
+            BinaryOp::Sub => '-',
+            BinaryOp::Mul => '*',
+            BinaryOp::Div => '/',
+            BinaryOp::And => '&',
+            BinaryOp::Or => '|',
+            BinaryOp::Lt => '<',
+            BinaryOp::Gt => '>',
+            BinaryOp::Eq => '=',
+        }
+    }
+
+    /// 記号に対応する演算子を返す。演算子でなければNoneを返す
+    pub fn from_symbol(c: char) -> Option<BinaryOp> {
+        let op = match c {
+            '+' => BinaryOp::Add,
+            '-' => BinaryOp::Sub,
+            '*' => BinaryOp::Mul,
+            '/' => BinaryOp::Div,
+            '&' => BinaryOp::And,
+            '|' => BinaryOp::Or,
+            '<' => BinaryOp::Lt,
+            '>' => BinaryOp::Gt,
+            '=' => BinaryOp::Eq,
+            _ => return None
+        };
+        Some(op)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+impl UnaryOp {
+    /// 演算子に対応する記号を返す
+    pub fn symbol(self) -> char {
+        match self {
+            UnaryOp::Neg => '-',
+            UnaryOp::Not => '~',
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeywordConstant {
+    True,
+    False,
+    Null,
+    This,
+}
+
+/// integerConstant | stringConstant | keywordConstant | varName |
+/// varName '[' expression ']' | subroutineCall | '(' expression ')' |
+/// unaryOp term
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    IntegerConstant {
+        value: usize,
+        span: Span,
+    },
+    StringConstant {
+        value: String,
+        span: Span,
+    },
+    KeywordConstant {
+        value: KeywordConstant,
+        span: Span,
+    },
+    VarName(Identifier),
+    ArrayAccess {
+        name: Identifier,
+        index: Box<Expression>,
+        span: Span,
+    },
+    SubroutineCall(SubroutineCall),
+    Parenthesized {
+        expression: Box<Expression>,
+        span: Span,
+    },
+    Unary {
+        op: UnaryOp,
+        term: Box<Term>,
+        span: Span,
+    },
+}
+
+/// subroutineName '(' expressionList ')' |
+/// (className | varName) '.' subroutineName '(' expressionList ')'
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubroutineCall {
+    /// ドットの前のクラス名か変数名。自身のメソッドの呼び出しのときはNone
+    pub receiver: Option<Identifier>,
+    pub name: Identifier,
+    pub arguments: Vec<Expression>,
+    pub span: Span,
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::BinaryOp;
+    use super::Type;
+
+    #[test]
+    fn test_binary_op_symbol() {
+        for c in "+-*/&|<>=".chars() {
+            assert_eq!(BinaryOp::from_symbol(c).unwrap().symbol(), c);
+        }
+        assert_eq!(BinaryOp::from_symbol('~'), None);
+    }
+
+    #[test]
+    fn test_type_from_name() {
+        for name in &["int", "char", "boolean", "Array"] {
+            assert_eq!(Type::from_name(name).name(), *name);
+        }
+        assert_eq!(Type::from_name("String"), Type::Class("String".to_string()));
+    }
+}
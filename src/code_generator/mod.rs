@@ -0,0 +1,613 @@
+//! CodeGeneratorは構文木からvmコードを生成する。
+//! 変数の情報はSymbolTableで管理し、書き込みはVMWriterで行う
+
+use std::io;
+use std::io::Write;
+
+use super::ast::*;
+use super::symbol_table::SymbolTable;
+use super::symbol_table::Kind;
+use super::symbol_table::SymbolInfo;
+use super::vm_writer::VMWriter;
+use super::vm_writer::Segment;
+use super::vm_writer::Command;
+use super::error::CompileError;
+use super::tokenizer::token::MAX_INTEGER;
+
+
+// 文字をHackの文字コードに変換する。Hackの文字セットで表示できるのは
+// スペースから~までのASCII文字なので、それ以外の文字は?にする
+fn hack_char(c: char) -> usize {
+    match c {
+        ' '..='~' => c as usize,
+        _ => '?' as usize,
+    }
+}
+
+pub struct CodeGenerator<'a, W> {
+    output: VMWriter<&'a mut W>,
+    symbol_table: SymbolTable,
+    // コンパイル中のクラス名
+    class_name: String,
+    // ラベルを一意にするためのカウンタ
+    label_count: usize,
+}
+
+impl<'a, W: Write> CodeGenerator<'a, W> {
+    pub fn new(output: &'a mut W) -> CodeGenerator<'a, W> {
+        CodeGenerator {
+            output: VMWriter::new(output),
+            symbol_table: SymbolTable::new(),
+            class_name: String::new(),
+            label_count: 0,
+        }
+    }
+
+    // ラベル名を一意にするための番号を返す
+    fn new_label_id(&mut self) -> usize {
+        let id = self.label_count;
+        self.label_count += 1;
+        id
+    }
+
+    // 変数の情報を返す。見つからなければエラーを返す
+    fn lookup(&self, name: &Identifier) -> Result<&SymbolInfo, CompileError> {
+        self.symbol_table.lookup(&name.name).ok_or_else(|| CompileError::UndefinedVariable {
+            name: name.name.clone(),
+            span: name.span.clone(),
+        })
+    }
+
+    // 変数をスタックにpushするvmコードを書く
+    fn push_variable(&mut self, name: &Identifier) -> Result<(), CompileError> {
+        let (segment, index) = self.lookup(name).map(|info| (info.segment(), info.index))?;
+        self.output.write_push(segment, index);
+        Ok(())
+    }
+
+    // スタックの先頭を変数にpopするvmコードを書く
+    fn pop_variable(&mut self, name: &Identifier) -> Result<(), CompileError> {
+        let (segment, index) = self.lookup(name).map(|info| (info.segment(), info.index))?;
+        self.output.write_pop(segment, index);
+        Ok(())
+    }
+
+    /// 書き込みを終える。途中で書き込めなかったときはそのエラーを返す
+    pub fn finish(self) -> io::Result<()> {
+        self.output.finish()
+    }
+
+    /// クラスのvmコードを書く
+    pub fn generate_class(&mut self, class: &Class) -> Result<(), CompileError> {
+        self.class_name = class.name.name.clone();
+        self.symbol_table = SymbolTable::new();
+
+        if let Some(e) = self.symbol_table.define_class_vars(class).into_iter().next() {
+            return Err(e)
+        }
+
+        for subroutine_dec in &class.subroutine_decs {
+            self.generate_subroutine(subroutine_dec)?;
+        }
+
+        Ok(())
+    }
+
+    fn generate_subroutine(&mut self, subroutine_dec: &SubroutineDec)
+        -> Result<(), CompileError> {
+        let errors = self.symbol_table.enter_subroutine(&self.class_name, subroutine_dec);
+        if let Some(e) = errors.into_iter().next() {
+            return Err(e)
+        }
+
+        let name = format!("{}.{}", self.class_name, subroutine_dec.name.name);
+        self.output.write_function(&name, self.symbol_table.var_count(Kind::Var));
+
+        match subroutine_dec.kind {
+            // コンストラクタの場合はフィールドの分だけメモリを確保する
+            SubroutineKind::Constructor => {
+                self.output.write_push(Segment::Constant,
+                                       self.symbol_table.var_count(Kind::Field));
+                self.output.write_call("Memory.alloc", 1);
+                self.output.write_pop(Segment::Pointer, 0);
+            },
+            // メソッドの場合は最初の引数をthisにする
+            SubroutineKind::Method => {
+                self.output.write_push(Segment::Argument, 0);
+                self.output.write_pop(Segment::Pointer, 0);
+            },
+            SubroutineKind::Function => ()
+        }
+
+        self.generate_statements(&subroutine_dec.statements)
+    }
+
+    fn generate_statements(&mut self, statements: &[Statement])
+        -> Result<(), CompileError> {
+        for statement in statements {
+            self.generate_statement(statement)?;
+        }
+        Ok(())
+    }
+
+    fn generate_statement(&mut self, statement: &Statement) -> Result<(), CompileError> {
+        match statement {
+            Statement::Let { name, index: Some(index), value, .. } => {
+                // 代入先のアドレスをスタックに積んでおく
+                self.push_variable(name)?;
+                self.generate_expression(index)?;
+                self.output.write_arithmetic(Command::Add);
+
+                self.generate_expression(value)?;
+
+                // 右辺の値を退避してからthatで代入先を指す
+                self.output.write_pop(Segment::Temp, 0);
+                self.output.write_pop(Segment::Pointer, 1);
+                self.output.write_push(Segment::Temp, 0);
+                self.output.write_pop(Segment::That, 0);
+            },
+            Statement::Let { name, index: None, value, .. } => {
+                self.generate_expression(value)?;
+                self.pop_variable(name)?;
+            },
+            Statement::If { condition, then_statements, else_statements, .. } => {
+                let id = self.new_label_id();
+                let false_label = format!("IF_FALSE{}", id);
+                let end_label = format!("IF_END{}", id);
+
+                self.generate_expression(condition)?;
+                self.output.write_arithmetic(Command::Not);
+                self.output.write_if(&false_label);
+
+                self.generate_statements(then_statements)?;
+                self.output.write_goto(&end_label);
+
+                self.output.write_label(&false_label);
+                if let Some(else_statements) = else_statements {
+                    self.generate_statements(else_statements)?;
+                }
+                self.output.write_label(&end_label);
+            },
+            Statement::While { condition, statements, .. } => {
+                let id = self.new_label_id();
+                let exp_label = format!("WHILE_EXP{}", id);
+                let end_label = format!("WHILE_END{}", id);
+
+                self.output.write_label(&exp_label);
+                self.generate_expression(condition)?;
+                self.output.write_arithmetic(Command::Not);
+                self.output.write_if(&end_label);
+
+                self.generate_statements(statements)?;
+                self.output.write_goto(&exp_label);
+                self.output.write_label(&end_label);
+            },
+            Statement::Do { call, .. } => {
+                self.generate_subroutine_call(call)?;
+                // 戻り値は使わないので捨てる
+                self.output.write_pop(Segment::Temp, 0);
+            },
+            Statement::Return { value, .. } => {
+                match value {
+                    Some(value) => self.generate_expression(value)?,
+                    // voidの関数も0を返す
+                    None => self.output.write_push(Segment::Constant, 0),
+                }
+                self.output.write_return();
+            },
+        }
+
+        Ok(())
+    }
+
+    fn generate_expression(&mut self, expression: &Expression)
+        -> Result<(), CompileError> {
+        self.generate_term(&expression.term)?;
+
+        // 後置記法なので両方の項を積んだ後に演算する
+        for (op, term) in &expression.rest {
+            self.generate_term(term)?;
+            match op {
+                BinaryOp::Add => self.output.write_arithmetic(Command::Add),
+                BinaryOp::Sub => self.output.write_arithmetic(Command::Sub),
+                BinaryOp::Mul => self.output.write_call("Math.multiply", 2),
+                BinaryOp::Div => self.output.write_call("Math.divide", 2),
+                BinaryOp::And => self.output.write_arithmetic(Command::And),
+                BinaryOp::Or => self.output.write_arithmetic(Command::Or),
+                BinaryOp::Lt => self.output.write_arithmetic(Command::Lt),
+                BinaryOp::Gt => self.output.write_arithmetic(Command::Gt),
+                BinaryOp::Eq => self.output.write_arithmetic(Command::Eq),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn generate_term(&mut self, term: &Term) -> Result<(), CompileError> {
+        match term {
+            Term::IntegerConstant { value, .. } => {
+                self.output.write_push(Segment::Constant, *value);
+            },
+            Term::StringConstant { value, .. } => {
+                // 文字列はString.newで作り、1文字ずつ追加する。
+                // ASCII以外の文字も1文字として数える
+                self.output.write_push(Segment::Constant, value.chars().count());
+                self.output.write_call("String.new", 1);
+                for c in value.chars() {
+                    self.output.write_push(Segment::Constant, hack_char(c));
+                    self.output.write_call("String.appendChar", 2);
+                }
+            },
+            Term::KeywordConstant { value, .. } => match value {
+                // trueは-1なので0をビット反転する
+                KeywordConstant::True => {
+                    self.output.write_push(Segment::Constant, 0);
+                    self.output.write_arithmetic(Command::Not);
+                },
+                // falseとnullは0
+                KeywordConstant::False | KeywordConstant::Null => {
+                    self.output.write_push(Segment::Constant, 0);
+                },
+                KeywordConstant::This => {
+                    self.output.write_push(Segment::Pointer, 0);
+                },
+            },
+            Term::VarName(name) => self.push_variable(name)?,
+            Term::ArrayAccess { name, index, .. } => {
+                self.push_variable(name)?;
+                self.generate_expression(index)?;
+                self.output.write_arithmetic(Command::Add);
+                self.output.write_pop(Segment::Pointer, 1);
+                self.output.write_push(Segment::That, 0);
+            },
+            Term::SubroutineCall(call) => self.generate_subroutine_call(call)?,
+            Term::Parenthesized { expression, .. } => {
+                self.generate_expression(expression)?;
+            },
+            // 32768はpushできないので、-32768は32767のビット反転で作る
+            Term::Unary { op: UnaryOp::Neg, term, .. } if matches!(
+                **term, Term::IntegerConstant { value, .. } if value == MAX_INTEGER + 1) => {
+                self.output.write_push(Segment::Constant, MAX_INTEGER);
+                self.output.write_arithmetic(Command::Not);
+            },
+            Term::Unary { op, term, .. } => {
+                self.generate_term(term)?;
+                self.output.write_arithmetic(match op {
+                    UnaryOp::Neg => Command::Neg,
+                    UnaryOp::Not => Command::Not,
+                });
+            },
+        }
+
+        Ok(())
+    }
+
+    // レシーバと引数をpushしてからサブルーチンを呼び出す。
+    // レシーバが変数ならそのオブジェクトを、レシーバがなければ自身(this)を
+    // 最初の引数として渡す。それ以外の場合はクラス名として扱う
+    fn generate_subroutine_call(&mut self, call: &SubroutineCall)
+        -> Result<(), CompileError> {
+        let (full_name, n) = match &call.receiver {
+            Some(r) => match self.symbol_table.type_of(&r.name) {
+                Some(ty) => {
+                    let full_name = format!("{}.{}", ty, call.name.name);
+                    self.push_variable(r)?;
+                    (full_name, 1)
+                },
+                None => (format!("{}.{}", r.name, call.name.name), 0)
+            },
+            None => {
+                self.output.write_push(Segment::Pointer, 0);
+                (format!("{}.{}", self.class_name, call.name.name), 1)
+            }
+        };
+
+        for argument in &call.arguments {
+            self.generate_expression(argument)?;
+        }
+
+        self.output.write_call(&full_name, n + call.arguments.len());
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::hack_char;
+    use super::CodeGenerator;
+    use crate::compilation_engine::parse_str;
+
+    // クラスのvmコードを生成する
+    fn generate(source: &str) -> String {
+        let class = parse_str(source).unwrap();
+        let mut output = Vec::new();
+        let mut g = CodeGenerator::new(&mut output);
+        g.generate_class(&class).unwrap();
+        g.finish().unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    // vmコードの行を改行でつなげる
+    fn vm(lines: &[&str]) -> String {
+        lines.iter().map(|l| format!("{}\n", l)).collect()
+    }
+
+    #[test]
+    fn test_hack_char() {
+        assert_eq!(hack_char('A'), 65);
+        assert_eq!(hack_char(' '), 32);
+        assert_eq!(hack_char('~'), 126);
+        assert_eq!(hack_char('あ'), 63);
+        assert_eq!(hack_char('\t'), 63);
+    }
+
+    #[test]
+    fn test_seven() {
+        let source = "class Main {
+            function void main() {
+                do Output.printInt(1 + (2 * 3));
+                return;
+            }
+        }";
+        assert_eq!(generate(source), vm(&[
+            "function Main.main 0",
+            "push constant 1",
+            "push constant 2",
+            "push constant 3",
+            "call Math.multiply 2",
+            "add",
+            "call Output.printInt 1",
+            "pop temp 0",
+            "push constant 0",
+            "return",
+        ]));
+    }
+
+    #[test]
+    fn test_convert_to_bin() {
+        // whileとif/elseのラベルは入れ子になっても別の番号にする
+        let source = "class Main {
+            function void convert(int value) {
+                var int mask, position;
+                var boolean loop;
+                let loop = true;
+                while (loop) {
+                    let position = position + 1;
+                    let mask = Main.nextMask(mask);
+                    if (~(position > 16)) {
+                        if (~((value & mask) = 0)) {
+                            do Memory.poke(8000 + position, 1);
+                        } else {
+                            do Memory.poke(8000 + position, 0);
+                        }
+                    } else {
+                        let loop = false;
+                    }
+                }
+                return;
+            }
+
+            function int nextMask(int mask) {
+                if (mask = 0) {
+                    return 1;
+                } else {
+                    return mask * 2;
+                }
+            }
+        }";
+        assert_eq!(generate(source), vm(&[
+            "function Main.convert 3",
+            "push constant 0",
+            "not",
+            "pop local 2",
+            "label WHILE_EXP0",
+            "push local 2",
+            "not",
+            "if-goto WHILE_END0",
+            "push local 1",
+            "push constant 1",
+            "add",
+            "pop local 1",
+            "push local 0",
+            "call Main.nextMask 1",
+            "pop local 0",
+            "push local 1",
+            "push constant 16",
+            "gt",
+            "not",
+            "not",
+            "if-goto IF_FALSE1",
+            "push argument 0",
+            "push local 0",
+            "and",
+            "push constant 0",
+            "eq",
+            "not",
+            "not",
+            "if-goto IF_FALSE2",
+            "push constant 8000",
+            "push local 1",
+            "add",
+            "push constant 1",
+            "call Memory.poke 2",
+            "pop temp 0",
+            "goto IF_END2",
+            "label IF_FALSE2",
+            "push constant 8000",
+            "push local 1",
+            "add",
+            "push constant 0",
+            "call Memory.poke 2",
+            "pop temp 0",
+            "label IF_END2",
+            "goto IF_END1",
+            "label IF_FALSE1",
+            "push constant 0",
+            "pop local 2",
+            "label IF_END1",
+            "goto WHILE_EXP0",
+            "label WHILE_END0",
+            "push constant 0",
+            "return",
+            "function Main.nextMask 0",
+            "push argument 0",
+            "push constant 0",
+            "eq",
+            "not",
+            "if-goto IF_FALSE3",
+            "push constant 1",
+            "return",
+            "goto IF_END3",
+            "label IF_FALSE3",
+            "push argument 0",
+            "push constant 2",
+            "call Math.multiply 2",
+            "return",
+            "label IF_END3",
+        ]));
+    }
+
+    #[test]
+    fn test_array() {
+        // 代入先のアドレスを先に計算し、右辺の値はtempに退避する
+        let source = "class Main {
+            function int main(int i) {
+                var Array a;
+                let a = Array.new(3);
+                let a[i] = a[0] + 2;
+                return a[i];
+            }
+        }";
+        assert_eq!(generate(source), vm(&[
+            "function Main.main 1",
+            "push constant 3",
+            "call Array.new 1",
+            "pop local 0",
+            "push local 0",
+            "push argument 0",
+            "add",
+            "push local 0",
+            "push constant 0",
+            "add",
+            "pop pointer 1",
+            "push that 0",
+            "push constant 2",
+            "add",
+            "pop temp 0",
+            "pop pointer 1",
+            "push temp 0",
+            "pop that 0",
+            "push local 0",
+            "push argument 0",
+            "add",
+            "pop pointer 1",
+            "push that 0",
+            "return",
+        ]));
+    }
+
+    #[test]
+    fn test_square() {
+        // コンストラクタはフィールドの数だけメモリを確保し、メソッドはthisを設定する。
+        // レシーバのない呼び出しは自身を最初の引数にする
+        let source = "class Square {
+            field int x, y, size;
+
+            constructor Square new(int Ax, int Ay, int Asize) {
+                let x = Ax;
+                let y = Ay;
+                let size = Asize;
+                do draw();
+                return this;
+            }
+
+            method void draw() {
+                do Screen.drawRectangle(x, y, x + size, y + size);
+                return;
+            }
+        }";
+        assert_eq!(generate(source), vm(&[
+            "function Square.new 0",
+            "push constant 3",
+            "call Memory.alloc 1",
+            "pop pointer 0",
+            "push argument 0",
+            "pop this 0",
+            "push argument 1",
+            "pop this 1",
+            "push argument 2",
+            "pop this 2",
+            "push pointer 0",
+            "call Square.draw 1",
+            "pop temp 0",
+            "push pointer 0",
+            "return",
+            "function Square.draw 0",
+            "push argument 0",
+            "pop pointer 0",
+            "push this 0",
+            "push this 1",
+            "push this 0",
+            "push this 2",
+            "add",
+            "push this 1",
+            "push this 2",
+            "add",
+            "call Screen.drawRectangle 4",
+            "pop temp 0",
+            "push constant 0",
+            "return",
+        ]));
+    }
+
+    #[test]
+    fn test_square_game() {
+        // フィールドやローカル変数のメソッドはそのオブジェクトを最初の引数にして、
+        // 変数の型のクラスの関数として呼び出す
+        let source = "class SquareGame {
+            field Square square;
+
+            method void moveUp() {
+                do square.draw();
+                return;
+            }
+
+            function void run() {
+                var SquareGame game;
+                let game = SquareGame.new();
+                do game.moveUp();
+                do Output.printString(\"Hi!\");
+                return;
+            }
+        }";
+        assert_eq!(generate(source), vm(&[
+            "function SquareGame.moveUp 0",
+            "push argument 0",
+            "pop pointer 0",
+            "push this 0",
+            "call Square.draw 1",
+            "pop temp 0",
+            "push constant 0",
+            "return",
+            "function SquareGame.run 1",
+            "call SquareGame.new 0",
+            "pop local 0",
+            "push local 0",
+            "call SquareGame.moveUp 1",
+            "pop temp 0",
+            "push constant 3",
+            "call String.new 1",
+            "push constant 72",
+            "call String.appendChar 2",
+            "push constant 105",
+            "call String.appendChar 2",
+            "push constant 33",
+            "call String.appendChar 2",
+            "call Output.printString 1",
+            "pop temp 0",
+            "push constant 0",
+            "return",
+        ]));
+    }
+}
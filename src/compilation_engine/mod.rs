@@ -1,653 +1,846 @@
-use std::io::{Read, Write, Seek};
+use std::io;
+use std::io::{Read, Write};
+use std::mem;
+use std::rc::Rc;
 
 use super::tokenizer::Tokenizer;
 use super::tokenizer::token::Token;
 use super::tokenizer::token::Keyword;
-
-
-#[cfg(debug_assertions)]
+use super::tokenizer::token::MAX_INTEGER;
+use super::span::Span;
+use super::ast::*;
+use super::xml_writer::XmlWriter;
+use super::json_writer;
+use super::code_generator::CodeGenerator;
+use super::resolver::Resolver;
+use super::type_checker::TypeChecker;
+use super::signature::{Registry, CallChecker};
+use super::error::CompileError;
+
+
+// ファイルの終わりに達したときのエラーを返す
 macro_rules! ErrReachedEnd {
-    () => {
-        Err(format!("reached end of file while parsing. DEBUG: line {}", 
-                    line!()))
-    };
-}
-
-#[cfg(not(debug_assertions))]
-macro_rules! ErrReachedEnd {
-    () => {
-        Err("reached end of file while parsing".to_string())
-    };
-}
-
-#[cfg(debug_assertions)]
-macro_rules! ErrUnexpect {
-    ($token:expr, $line_number:expr) => {
-        Err(format!("unexpected token: '{}' at line {}. DEBUG: line {}", 
-                    $token.to_string(),
-                    $line_number,
-                    line!()))
+    ($self:ident $(, $expected_token:pat )*) => {
+        Err($self.eof_error(vec![$( expected_name(stringify!($expected_token)) ),*]))
     };
 }
 
-#[cfg(not(debug_assertions))]
+// 予期しないトークンのエラーを返す。expected_tokenはその位置で受け付ける
+// トークンのパターン
 macro_rules! ErrUnexpect {
-    ($token:expr, $line_number:expr) => {
-        Err(format!("unexpected token: '{}' at line {}.", 
-                    $token.to_string(),
-                    $line_number))
+    ($self:ident, $token:expr $(, $expected_token:pat )*) => {
+        Err(CompileError::UnexpectedToken {
+            found: $token.to_string(),
+            expected: vec![$( expected_name(stringify!($expected_token)) ),*],
+            span: $self.span(),
+        })
     };
 }
 
 // 引数のトークンにマッチしたらそのトークンを返す。
 // そうでなければエラーをreturnする
 macro_rules! MatchToken {
-    ($token:expr, $line_number:expr, $( $expected_token:pat ),*) => {
+    ($self:ident, $token:expr, $( $expected_token:pat ),*) => {
         match $token {
             Some(t) => match t {
                 $(
                     $expected_token => t,
                 )*
                 // 予期しないトークンのときはエラーを返す
-                _ => return ErrUnexpect!(t, $line_number)
+                _ => return ErrUnexpect!($self, t, $( $expected_token ),*)
             },
             // tokenがNoneになるのは最後まで読み終えたときなのでエラーを返す
-            // javacのエラー文を流用
-            None => return ErrReachedEnd!()
+            None => return ErrReachedEnd!($self, $( $expected_token ),*)
         }
     };
 }
 
+// MatchToken!のパターンをエラーで表示するための名前に変換する。
+// 例えばToken::Symbol('{')は{に、Token::Keyword(Keyword::Class)はclassになる
+fn expected_name(pattern: &str) -> String {
+    let pattern: String = pattern.chars().filter(|c| !c.is_whitespace()).collect();
+    let pattern = pattern.trim_start_matches("Token::");
+    let (variant, inner) = match pattern.find('(') {
+        Some(i) => (&pattern[..i], pattern[i+1..].trim_end_matches(')')),
+        None => (pattern, "")
+    };
+
+    match variant {
+        "Symbol" => inner.trim_matches('\'').to_string(),
+        "Keyword" => inner.trim_start_matches("Keyword::").to_lowercase(),
+        "Identifier" => "identifier".to_string(),
+        "Integer" => "integer constant".to_string(),
+        "String" => "string constant".to_string(),
+        _ => pattern.to_string()
+    }
+}
+
+/// 1つのクラスで報告するエラーの最大数の初期値
+pub const DEFAULT_MAX_ERRORS: usize = 20;
+
+/// 出力の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// xmlの構文木
+    Xml,
+    /// vmコード
+    Vm,
+    /// jsonの構文木
+    Json,
+}
+
+/// tokenizerから構文木を作り、targetに応じた形式で出力する
 pub struct CompilationEngine<R, W> {
     pub tokenizer: Tokenizer<R>,
     output: W,
+    target: Target,
+    // 直前に読んだトークンの位置
+    prev_span: Span,
+    // 回復したエラー
+    errors: Vec<CompileError>,
+    // 報告するエラーの最大数
+    max_errors: usize,
+    // サブルーチンの変数がクラスの変数を隠すときに警告するか
+    warn_shadowing: bool,
+    // 変数の解決で見つけた警告
+    warnings: Vec<CompileError>,
+    // 型を確かめるか
+    strict_types: bool,
+    // 呼び出すサブルーチンのシグネチャ。Noneのときはこのクラスのものだけを使う
+    registry: Option<Rc<Registry>>,
 }
 
-impl<R: Read + Seek, W: Write> CompilationEngine<R, W> {
-    pub fn new(tokenizer: Tokenizer<R>, output: W) -> CompilationEngine<R, W> {
+// 型のトークンをTypeに変換する
+fn to_type(t: &Token) -> Type {
+    match t {
+        Token::Keyword(Keyword::Int) => Type::Int,
+        Token::Keyword(Keyword::Char) => Type::Char,
+        Token::Keyword(Keyword::Boolean) => Type::Boolean,
+        _ => Type::Class(t.to_string())
+    }
+}
+
+/// 文字列のソースコードを構文解析して構文木を返す。
+/// エラーから回復したときは最初のエラーを返す
+pub fn parse_str(source: &str) -> Result<Class, CompileError> {
+    let t = Tokenizer::new(source.as_bytes());
+    let mut c = CompilationEngine::new(t, io::sink(), Target::Xml);
+    c.tokenizer.advance();
+    let class = c.parse_class();
+    let mut errors = c.take_errors();
+    match class {
+        Ok(class) if errors.is_empty() => Ok(class),
+        Ok(_) => Err(errors.remove(0)),
+        Err(e) => Err(errors.into_iter().chain([e]).min_by_key(|e| {
+            e.span().map(|s| s.byte_range.start)
+        }).unwrap()),
+    }
+}
+
+impl<R: Read, W: Write> CompilationEngine<R, W> {
+    pub fn new(tokenizer: Tokenizer<R>, output: W, target: Target) 
+        -> CompilationEngine<R, W> {
         CompilationEngine {
             tokenizer,
-            output
+            output,
+            target,
+            prev_span: Span::default(),
+            errors: Vec::new(),
+            max_errors: DEFAULT_MAX_ERRORS,
+            warn_shadowing: false,
+            warnings: Vec::new(),
+            strict_types: false,
+            registry: None,
         }
     }
 
     /// tokenizerからクラスをコンパイルし、結果を書き込む。
-    /// 最初はvmコードではなくxmlの構文木を書き書き込む。
-    pub fn compile_class(&mut self) -> Result<(), String> {
-        let _ = self.output.write(b"<class>\n");
+    /// targetに応じてxmlかjsonの構文木、またはvmコードを書き込む。
+    /// 構文エラーがあっても解析を続け、見つけたエラーをまとめて返す。vmコードを
+    /// 書くときは宣言されていない変数や呼び出しの誤りもエラーにする。
+    /// エラーがあるときは何も書き込まない
+    pub fn compile_class(&mut self) -> Result<(), Vec<CompileError>> {
+        let class = self.parse_class();
+        let mut errors = self.take_errors();
+        let class = match class {
+            Ok(class) => class,
+            Err(e) => {
+                // エラーから回復した後や字句解析のエラーの後に終端に達したときの
+                // エラーは、前のエラーが原因なので報告しない
+                let cascaded = matches!(e, CompileError::UnexpectedEof { .. }) &&
+                               !errors.is_empty();
+                if !cascaded {
+                    errors.push(e);
+                    errors.sort_by_key(|e| e.span().map(|s| s.byte_range.start));
+                }
+                errors.truncate(self.max_errors);
+                return Err(errors)
+            }
+        };
 
-        let t = MatchToken!(self.tokenizer.get_current_token(),
-                            self.tokenizer.get_line_number(),
-                            Token::Keyword(Keyword::Class));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+        if !errors.is_empty() {
+            errors.truncate(self.max_errors);
+            return Err(errors)
+        }
 
-        let t = MatchToken!(self.tokenizer.advance(),
-                            self.tokenizer.get_line_number(),
-                            Token::Identifier(_));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+        // vmコードを書くときは、変数の参照を解決してから呼び出しと型を確かめる。
+        // 構文木は構文が正しければ書ける
+        if self.target == Target::Vm {
+            let mut resolver = Resolver::new();
+            resolver.set_warn_shadowing(self.warn_shadowing);
+            errors = resolver.resolve_class(&class).err().unwrap_or_default();
+            self.warnings.append(&mut resolver.take_warnings());
+        }
+        if self.target == Target::Vm && errors.is_empty() {
+            let mut local;
+            let registry = match &self.registry {
+                Some(registry) => registry.as_ref(),
+                None => {
+                    local = Registry::with_os();
+                    local.add_class(&class);
+                    &local
+                }
+            };
+            errors.extend(CallChecker::new(registry, &class).check_class().err()
+                .unwrap_or_default());
+            if self.strict_types {
+                errors.extend(TypeChecker::new(registry, &class).check_class().err()
+                    .unwrap_or_default());
+            }
+            errors.sort_by_key(|e| e.span().map(|s| s.byte_range.start));
+        }
+        if !errors.is_empty() {
+            errors.truncate(self.max_errors);
+            return Err(errors)
+        }
+
+        // 書き込みのエラーは書き終えてからまとめて確かめる
+        let result = match self.target {
+            Target::Xml => {
+                let mut w = XmlWriter::new(&mut self.output);
+                w.write_class(&class);
+                w.finish()
+            },
+            Target::Json => {
+                let json = json_writer::class(&class).to_pretty();
+                self.output.write_all(json.as_bytes()).and_then(|()| self.output.flush())
+            },
+            Target::Vm => {
+                let mut g = CodeGenerator::new(&mut self.output);
+                g.generate_class(&class).map_err(|e| vec![e])?;
+                g.finish()
+            },
+        };
+        result.map_err(|e| vec![CompileError::io("<output>", &e)])
+    }
 
-        let t = MatchToken!(self.tokenizer.advance(),
-                            self.tokenizer.get_line_number(),
-                            Token::Symbol('{'));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+    /// 報告するエラーの最大数を設定する。これに達したら解析をやめる。
+    /// 0のときは1とする
+    pub fn set_max_errors(&mut self, max_errors: usize) {
+        self.max_errors = max_errors.max(1);
+    }
 
-        // classVarDecもしくはsubroutineDec、'}'
-        while let Some(t) = self.tokenizer.advance() { 
+    /// trueにするとサブルーチンの変数や引数がクラスの変数を隠すときに警告する
+    pub fn set_warn_shadowing(&mut self, warn_shadowing: bool) {
+        self.warn_shadowing = warn_shadowing;
+    }
+
+    /// 他のクラスのサブルーチンのシグネチャを設定する。設定しないときは
+    /// Jack OSとコンパイルするクラスのものだけで呼び出しを確かめる
+    pub fn set_registry(&mut self, registry: Rc<Registry>) {
+        self.registry = Some(registry);
+    }
+
+    /// trueにすると書き込む前に型を確かめ、型の誤りをエラーとして返す
+    pub fn set_strict_types(&mut self, strict_types: bool) {
+        self.strict_types = strict_types;
+    }
+
+    /// トークナイザと変数の解決の警告をソースコードの順に取り出す
+    pub fn take_warnings(&mut self) -> Vec<CompileError> {
+        let mut warnings = self.tokenizer.take_warnings();
+        warnings.append(&mut self.warnings);
+        warnings.sort_by_key(|w| w.span().map(|s| s.byte_range.start));
+        warnings
+    }
+
+    // 構文解析と字句解析のエラーをソースコードの順に取り出す
+    fn take_errors(&mut self) -> Vec<CompileError> {
+        let mut errors = mem::take(&mut self.errors);
+        errors.append(&mut self.tokenizer.take_errors());
+        errors.sort_by_key(|e| e.span().map(|s| s.byte_range.start));
+        errors
+    }
+
+    // エラーを記録して解析を続けられるならOkを返す。
+    // 終端に達している場合やエラーの数が上限に達した場合はErrを返す
+    fn recover(&mut self, e: CompileError) -> Result<(), CompileError> {
+        if self.tokenizer.get_current_token().is_none() ||
+           self.max_errors <= self.errors.len() + 1 {
+            return Err(e)
+        }
+
+        self.errors.push(e);
+        Ok(())
+    }
+
+    // 文の区切りまでトークンを読み飛ばす。';'は読み飛ばし、
+    // 文の始まりのキーワードや'}'、サブルーチンの宣言の手前で止まる
+    fn skip_to_statement(&mut self) {
+        while let Some(t) = self.tokenizer.get_current_token() {
             match t {
-                // classVarDecの場合 
-                Token::Keyword(Keyword::Static) | 
-                Token::Keyword(Keyword::Field) => {
-                    self.class_var_dec()?;
+                Token::Symbol(';') => {
+                    self.advance();
+                    return
                 },
-                // subroutineDecの場合
+                Token::Symbol('}') |
+                Token::Keyword(Keyword::Let) |
+                Token::Keyword(Keyword::Do) |
+                Token::Keyword(Keyword::If) |
+                Token::Keyword(Keyword::While) |
+                Token::Keyword(Keyword::Return) |
+                Token::Keyword(Keyword::Static) |
+                Token::Keyword(Keyword::Field) |
                 Token::Keyword(Keyword::Constructor) |
-                Token::Keyword(Keyword::Function) | 
-                Token::Keyword(Keyword::Method) => {
-                    self.compile_subroutine()?;
+                Token::Keyword(Keyword::Function) |
+                Token::Keyword(Keyword::Method) => return,
+                _ => {
+                    self.advance();
+                }
+            }
+        }
+    }
+
+    // 次のクラス変数の宣言かサブルーチンの宣言の手前までトークンを読み飛ばす
+    fn skip_to_class_member(&mut self) {
+        while let Some(t) = self.tokenizer.get_current_token() {
+            match t {
+                Token::Keyword(Keyword::Static) |
+                Token::Keyword(Keyword::Field) |
+                Token::Keyword(Keyword::Constructor) |
+                Token::Keyword(Keyword::Function) |
+                Token::Keyword(Keyword::Method) => return,
+                _ => {
+                    self.advance();
+                }
+            }
+        }
+    }
+
+    // 現在のトークンの位置
+    fn span(&self) -> Span {
+        self.tokenizer.current_span().clone()
+    }
+
+    // トークンが尽きたときのエラーを返す
+    fn eof_error(&mut self, expected: Vec<String>) -> CompileError {
+        CompileError::UnexpectedEof { expected, span: self.span() }
+    }
+
+    // トークンを進める。進める前のトークンの位置はprev_spanに残しておく
+    fn advance(&mut self) -> Option<&Token> {
+        self.prev_span = self.span();
+        self.tokenizer.advance()
+    }
+
+    // 現在のトークンを識別子として返す
+    fn current_identifier(&self) -> Identifier {
+        Identifier {
+            name: self.tokenizer.get_current_token().unwrap().to_string(),
+            span: self.span(),
+        }
+    }
+
+    /// tokenizerからクラスを読み、構文木を返す
+    pub fn parse_class(&mut self) -> Result<Class, CompileError> {
+        let start = self.span();
+        MatchToken!(self, self.tokenizer.get_current_token(),
+                    Token::Keyword(Keyword::Class));
+
+        MatchToken!(self, self.advance(),
+                    Token::Identifier(_));
+        let name = self.current_identifier();
+
+        MatchToken!(self, self.advance(),
+                    Token::Symbol('{'));
+
+        let mut class_var_decs = Vec::new();
+        let mut subroutine_decs = Vec::new();
+
+        // classVarDecもしくはsubroutineDec、'}'
+        self.advance();
+        loop {
+            let result = match self.tokenizer.get_current_token() {
+                Some(t) => match t {
+                    // classVarDecの場合
+                    Token::Keyword(Keyword::Static) |
+                    Token::Keyword(Keyword::Field) => {
+                        self.parse_class_var_dec().map(|d| class_var_decs.push(d))
+                    },
+                    // subroutineDecの場合
+                    Token::Keyword(Keyword::Constructor) |
+                    Token::Keyword(Keyword::Function) |
+                    Token::Keyword(Keyword::Method) => {
+                        self.parse_subroutine().map(|d| subroutine_decs.push(d))
+                    },
+                    // '}'まで読み終えたらクラスを返す。その後にトークンが
+                    // あればエラーにする
+                    Token::Symbol('}') => {
+                        let span = start.to(&self.span());
+                        if let Some(t) = self.advance() {
+                            return ErrUnexpect!(self, t)
+                        }
+                        return Ok(Class { name, class_var_decs, subroutine_decs, span })
+                    },
+                    // それ以外はエラーになる
+                    _ => ErrUnexpect!(self, t,
+                                      Token::Keyword(Keyword::Static),
+                                      Token::Keyword(Keyword::Field),
+                                      Token::Keyword(Keyword::Constructor),
+                                      Token::Keyword(Keyword::Function),
+                                      Token::Keyword(Keyword::Method),
+                                      Token::Symbol('}'))
                 },
-                // '}'まで読み終えたらOk(())を返す
-                Token::Symbol('}') => {
-                    let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-                    let _ = self.output.write(b"</class>\n");
-                    return Ok(())
+                // '}'を見つけずに最後まで読み終えたらエラーを返す
+                None => return ErrReachedEnd!(self, Token::Symbol('}'))
+            };
+
+            match result {
+                Ok(()) => {
+                    self.advance();
                 },
-                // それ以外はエラーになる
-                _ => return ErrUnexpect!(t, self.tokenizer.get_line_number())
+                // エラーのときは次の宣言から解析を続ける
+                Err(e) => {
+                    self.recover(e)?;
+                    self.skip_to_class_member();
+                }
             }
         }
-
-        // '}'を見つけずに最後まで読み終えたらエラーを返す
-        ErrReachedEnd!()
     }
     
-    fn class_var_dec(&mut self) -> Result<(), String> {
-        let _ = self.output.write(b"<classVarDec>\n");
-
-        let t = MatchToken!(self.tokenizer.get_current_token(),
-                            self.tokenizer.get_line_number(),
+    fn parse_class_var_dec(&mut self) -> Result<ClassVarDec, CompileError> {
+        let start = self.span();
+        let t = MatchToken!(self, self.tokenizer.get_current_token(),
                             Token::Keyword(Keyword::Static),
                             Token::Keyword(Keyword::Field));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+        let kind = match t {
+            Token::Keyword(Keyword::Static) => ClassVarKind::Static,
+            _ => ClassVarKind::Field
+        };
 
-        let t = MatchToken!(self.tokenizer.advance(),
-                            self.tokenizer.get_line_number(),
+        let t = MatchToken!(self, self.advance(),
                             Token::Keyword(Keyword::Int),
                             Token::Keyword(Keyword::Char),
                             Token::Keyword(Keyword::Boolean),
                             Token::Identifier(_));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+        let ty = to_type(t);
 
-        let t = MatchToken!(self.tokenizer.advance(),
-                            self.tokenizer.get_line_number(),
-                            Token::Identifier(_));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+        MatchToken!(self, self.advance(),
+                    Token::Identifier(_));
+        let mut names = vec![self.current_identifier()];
 
-        while let Some(t) = self.tokenizer.advance() {
+        while let Some(t) = self.advance() {
             // 次に';'が来たらreturn、','が来たら繰り返す。それ以外ならエラーを
             // 返す
             match t {
                 Token::Symbol(';') => {
-                    let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-                    let _ = self.output.write(b"</classVarDec>\n");
-                    return Ok(())
+                    return Ok(ClassVarDec {
+                        kind, ty, names, span: start.to(&self.span())
+                    })
                 },
-                Token::Symbol(',') => {
-                    let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-                },
-                _ => return ErrUnexpect!(t, self.tokenizer.get_line_number())
+                Token::Symbol(',') => (),
+                _ => return ErrUnexpect!(self, t,
+                                         Token::Symbol(';'),
+                                         Token::Symbol(','))
             }
 
-            let t = MatchToken!(self.tokenizer.advance(),
-                                self.tokenizer.get_line_number(),
-                                Token::Identifier(_));
-            let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+            MatchToken!(self, self.advance(),
+                        Token::Identifier(_));
+            names.push(self.current_identifier());
         }
 
-        ErrReachedEnd!()
+        ErrReachedEnd!(self, Token::Symbol(';'), Token::Symbol(','))
     }
 
-    fn compile_subroutine(&mut self) -> Result<(), String> {
-        let _ = self.output.write(b"<subroutineDec>\n");
-
-        let t = MatchToken!(self.tokenizer.get_current_token(),
-                            self.tokenizer.get_line_number(),
+    fn parse_subroutine(&mut self) -> Result<SubroutineDec, CompileError> {
+        let start = self.span();
+        let t = MatchToken!(self, self.tokenizer.get_current_token(),
                             Token::Keyword(Keyword::Constructor),
                             Token::Keyword(Keyword::Function),
                             Token::Keyword(Keyword::Method));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+        let kind = match t {
+            Token::Keyword(Keyword::Constructor) => SubroutineKind::Constructor,
+            Token::Keyword(Keyword::Function) => SubroutineKind::Function,
+            _ => SubroutineKind::Method
+        };
 
-        let t = MatchToken!(self.tokenizer.advance(),
-                            self.tokenizer.get_line_number(),
+        let t = MatchToken!(self, self.advance(),
                             Token::Keyword(Keyword::Void),
                             Token::Keyword(Keyword::Int),
                             Token::Keyword(Keyword::Char),
                             Token::Keyword(Keyword::Boolean),
                             Token::Identifier(_));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+        let return_type = match t {
+            Token::Keyword(Keyword::Void) => None,
+            _ => Some(to_type(t))
+        };
 
-        let t = MatchToken!(self.tokenizer.advance(),
-                            self.tokenizer.get_line_number(),
-                            Token::Identifier(_));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+        MatchToken!(self, self.advance(),
+                    Token::Identifier(_));
+        let name = self.current_identifier();
 
-        let t = MatchToken!(self.tokenizer.advance(),
-                            self.tokenizer.get_line_number(),
-                            Token::Symbol('('));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+        MatchToken!(self, self.advance(),
+                    Token::Symbol('('));
 
-        self.tokenizer.advance();
-        self.compile_parameter_list()?;
+        self.advance();
+        let parameters = self.parse_parameter_list()?;
 
         // ここではadvanceを呼ばずに現在のトークンを使う
-        let t = MatchToken!(self.tokenizer.get_current_token(),
-                            self.tokenizer.get_line_number(),
-                            Token::Symbol(')'));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-
-        let _ = self.output.write(b"<subroutineBody>\n");
-
-        let t = MatchToken!(self.tokenizer.advance(),
-                            self.tokenizer.get_line_number(),
-                            Token::Symbol('{'));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-
-        while let Some(t) = self.tokenizer.advance() {
-            match t {
-                Token::Keyword(Keyword::Var) => {
-                    self.compile_var_dec()?;
+        MatchToken!(self, self.tokenizer.get_current_token(),
+                    Token::Symbol(')'));
+
+        MatchToken!(self, self.advance(),
+                    Token::Symbol('{'));
+
+        let mut var_decs = Vec::new();
+        self.advance();
+        while let Some(Token::Keyword(Keyword::Var)) = self.tokenizer.get_current_token() {
+            match self.parse_var_dec() {
+                Ok(var_dec) => {
+                    var_decs.push(var_dec);
+                    self.advance();
                 },
-                // varじゃなければbreakする
-                _ => break
+                // エラーのときは次の宣言か文から解析を続ける
+                Err(e) => {
+                    self.recover(e)?;
+                    self.skip_to_statement();
+                }
             }
         }
 
-        loop {
-            match self.tokenizer.get_current_token() {
-                Some(t) => match t {
-                    Token::Symbol('}') => {
-                        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-                        break
-                    },
-                    _ => self.compile_statements()?
-                    //_ => return ErrUnexpect!(t, self.tokenizer.get_line_number())
-                },
-                None => return ErrReachedEnd!()
-            }
-
-// self.compile_statementsですでにadvanceしているので、ここでadvanceをすると
-// 二重になってしまう(ので、エラーになる)
-//            self.tokenizer.advance();
-        }
+        let statements = self.parse_statements()?;
 
-        let _ = self.output.write(b"</subroutineBody>\n");
-        let _ = self.output.write(b"</subroutineDec>\n");
+        MatchToken!(self, self.tokenizer.get_current_token(),
+                    Token::Symbol('}'));
 
-        Ok(())
+        Ok(SubroutineDec {
+            kind,
+            return_type,
+            name,
+            parameters,
+            var_decs,
+            statements,
+            span: start.to(&self.span()),
+        })
     }
 
-    fn compile_parameter_list(&mut self) -> Result<(), String> {
-        let _ = self.output.write(b"<parameterList>\n");
+    fn parse_parameter_list(&mut self) -> Result<Vec<Parameter>, CompileError> {
+        let mut parameters = Vec::new();
 
         if let Some(Token::Symbol(')')) = self.tokenizer.get_current_token() {
             // parameter listが空のときはここでリターンする
-            let _ = self.output.write(b"</parameterList>\n");
-            return Ok(())
+            return Ok(parameters)
         }
 
         loop {
-            let t = MatchToken!(self.tokenizer.get_current_token(),
-                                self.tokenizer.get_line_number(),
+            let start = self.span();
+            let t = MatchToken!(self, self.tokenizer.get_current_token(),
                                 Token::Keyword(Keyword::Int),
                                 Token::Keyword(Keyword::Char),
                                 Token::Keyword(Keyword::Boolean),
                                 Token::Identifier(_));
-            let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+            let ty = to_type(t);
             
-            let t = MatchToken!(self.tokenizer.advance(),
-                                self.tokenizer.get_line_number(),
-                                Token::Identifier(_));
-            let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+            MatchToken!(self, self.advance(),
+                        Token::Identifier(_));
+            let name = self.current_identifier();
+            parameters.push(Parameter { ty, name, span: start.to(&self.span()) });
 
-            match self.tokenizer.advance() {
+            match self.advance() {
                 Some(t) => match t {
                     // 閉じカッコのときはbreakする
                     Token::Symbol(')') => break,
                     // ,のときはそのまま続ける
-                    Token::Symbol(',') => {
-                        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-                    },
-                    _ => return ErrUnexpect!(t, self.tokenizer.get_line_number())
+                    Token::Symbol(',') => (),
+                    _ => return ErrUnexpect!(self, t,
+                                             Token::Symbol(')'),
+                                             Token::Symbol(','))
                 },
-                None => return ErrReachedEnd!()
+                None => return ErrReachedEnd!(self, Token::Symbol(')'),
+                                              Token::Symbol(','))
             }
 
-            self.tokenizer.advance();
+            self.advance();
         }
 
-        let _ = self.output.write(b"</parameterList>\n");
-        Ok(())
+        Ok(parameters)
     }
 
-    fn compile_var_dec(&mut self) -> Result<(), String> {
-        let _ = self.output.write(b"<varDec>\n");
+    fn parse_var_dec(&mut self) -> Result<VarDec, CompileError> {
+        let start = self.span();
+        MatchToken!(self, self.tokenizer.get_current_token(),
+                    Token::Keyword(Keyword::Var));
 
-        let t = MatchToken!(self.tokenizer.get_current_token(),
-                            self.tokenizer.get_line_number(),
-                            Token::Keyword(Keyword::Var));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-
-        let t = MatchToken!(self.tokenizer.advance(),
-                            self.tokenizer.get_line_number(),
+        let t = MatchToken!(self, self.advance(),
                             Token::Keyword(Keyword::Int),
                             Token::Keyword(Keyword::Char),
                             Token::Keyword(Keyword::Boolean),
                             Token::Identifier(_));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+        let ty = to_type(t);
 
-        let t = MatchToken!(self.tokenizer.advance(),
-                            self.tokenizer.get_line_number(),
-                            Token::Identifier(_));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+        MatchToken!(self, self.advance(),
+                    Token::Identifier(_));
+        let mut names = vec![self.current_identifier()];
 
         loop {
-            match self.tokenizer.advance() {
+            match self.advance() {
                 Some(t) => match t {
                     // セミコロンだったらreturnする
                     Token::Symbol(';') => {
-                        let _ = self.output.write(
-                            (t.to_xml() + "\n").as_bytes());
-                        let _ = self.output.write(b"</varDec>\n");
-                        return Ok(())
+                        return Ok(VarDec { ty, names, span: start.to(&self.span()) })
                     },
-                    Token::Symbol(',') => {
-                        let _ = self.output.write(
-                            (t.to_xml() + "\n").as_bytes());
-                    },
-                    _ => return ErrUnexpect!(t, self.tokenizer.get_line_number())
+                    Token::Symbol(',') => (),
+                    _ => return ErrUnexpect!(self, t,
+                                             Token::Symbol(';'),
+                                             Token::Symbol(','))
                 },
-                None => return ErrReachedEnd!()
+                None => return ErrReachedEnd!(self, Token::Symbol(';'),
+                                              Token::Symbol(','))
             }
 
-            let t = MatchToken!(self.tokenizer.advance(),
-                                self.tokenizer.get_line_number(),
-                                Token::Keyword(Keyword::Int),
-                                Token::Keyword(Keyword::Char),
-                                Token::Keyword(Keyword::Boolean),
-                                Token::Identifier(_));
-            let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+            MatchToken!(self, self.advance(),
+                        Token::Identifier(_));
+            names.push(self.current_identifier());
         }
     }
 
-    fn compile_statements(&mut self) -> Result<(), String> {
-        let _ = self.output.write(b"<statements>\n");
+    fn parse_statements(&mut self) -> Result<Vec<Statement>, CompileError> {
+        let mut statements = Vec::new();
 
         loop {
-            match self.tokenizer.get_current_token() {
+            let statement = match self.tokenizer.get_current_token() {
                 Some(t) => match t {
-                    Token::Keyword(Keyword::Do) => self.compile_do()?,
-                    Token::Keyword(Keyword::Let) => self.compile_let_statement()?,
-                    Token::Keyword(Keyword::While) => self.compile_while()?,
-                    Token::Keyword(Keyword::Return) => self.compile_return()?,
-                    Token::Keyword(Keyword::If) => self.compile_if()?,
+                    Token::Keyword(Keyword::Do) => self.parse_do(),
+                    Token::Keyword(Keyword::Let) => self.parse_let_statement(),
+                    Token::Keyword(Keyword::While) => self.parse_while(),
+                    Token::Keyword(Keyword::Return) => self.parse_return(),
+                    Token::Keyword(Keyword::If) => self.parse_if(),
                     _ => break
                 },
-                None => return ErrReachedEnd!()
+                None => return ErrReachedEnd!(self, Token::Symbol('}'))
+            };
+
+            match statement {
+                Ok(statement) => statements.push(statement),
+                // エラーのときは次の文から解析を続ける
+                Err(e) => {
+                    self.recover(e)?;
+                    self.skip_to_statement();
+                }
             }
         }
 
-        let _ = self.output.write(b"</statements>\n");
-        Ok(())
+        Ok(statements)
     }
 
     /*
      * 'do' subroutineCall ';'
      * */
-    fn compile_do(&mut self) -> Result<(), String> {
-        let _ = self.output.write(b"<doStatement>\n");
-
-        let t = MatchToken!(self.tokenizer.get_current_token(),
-                            self.tokenizer.get_line_number(),
-                            Token::Keyword(Keyword::Do));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-
-        let t = MatchToken!(self.tokenizer.advance(),
-                            self.tokenizer.get_line_number(),
-                            Token::Identifier(_));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+    fn parse_do(&mut self) -> Result<Statement, CompileError> {
+        let start = self.span();
+        MatchToken!(self, self.tokenizer.get_current_token(),
+                    Token::Keyword(Keyword::Do));
 
-        match self.tokenizer.advance() {
-            Some(t) => match t {
-                // 関数呼び出しのとき
-                Token::Symbol('(') => {
-                    let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-                    self.compile_expression_list()?;
-
-                    let t = MatchToken!(self.tokenizer.get_current_token(),
-                                        self.tokenizer.get_line_number(),
-                                        Token::Symbol(')'));
-                    let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-
-                    self.tokenizer.advance();
-                },
-                // メソッド呼び出しのとき
-                Token::Symbol('.') => {
-                    let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+        MatchToken!(self, self.advance(),
+                    Token::Identifier(_));
+        let name = self.current_identifier();
 
-                    let t = MatchToken!(self.tokenizer.advance(),
-                                        self.tokenizer.get_line_number(),
-                                        Token::Identifier(_));
-                    let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+        self.advance();
+        let call = self.parse_subroutine_call(name)?;
 
-                    let t = MatchToken!(self.tokenizer.advance(),
-                                        self.tokenizer.get_line_number(),
-                                        Token::Symbol('('));
-                    let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+        MatchToken!(self, self.tokenizer.get_current_token(),
+                    Token::Symbol(';'));
 
-                    self.compile_expression_list()?;
-
-                    let t = MatchToken!(self.tokenizer.get_current_token(),
-                                        self.tokenizer.get_line_number(),
-                                        Token::Symbol(')'));
-                    let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-
-                    self.tokenizer.advance();
-                },
-                _ => return ErrUnexpect!(t, self.tokenizer.get_line_number())
-            },
-            None => return ErrReachedEnd!()
-        }
-
-        let t = MatchToken!(self.tokenizer.get_current_token(),
-                            self.tokenizer.get_line_number(),
-                            Token::Symbol(';'));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-
-        let _ = self.output.write(b"</doStatement>\n");
-        self.tokenizer.advance();
-        Ok(())
+        self.advance();
+        Ok(Statement::Do { call, span: start.to(&self.prev_span) })
     }
 
     /*
      * 'let' varName ('[' expression ']')? '=' expression ';'
      */
-    fn compile_let_statement(&mut self) -> Result<(), String> {
-        let _ = self.output.write(b"<letStatement>\n");
- 
-        let t = MatchToken!(self.tokenizer.get_current_token(),
-                            self.tokenizer.get_line_number(),
-                            Token::Keyword(Keyword::Let));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+    fn parse_let_statement(&mut self) -> Result<Statement, CompileError> {
+        let start = self.span();
+        MatchToken!(self, self.tokenizer.get_current_token(),
+                    Token::Keyword(Keyword::Let));
  
-        let t = MatchToken!(self.tokenizer.advance(),
-                            self.tokenizer.get_line_number(),
-                            Token::Identifier(_));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+        MatchToken!(self, self.advance(),
+                    Token::Identifier(_));
+        let name = self.current_identifier();
+        let mut index = None;
 
-        match self.tokenizer.advance() {
+        match self.advance() {
             Some(t) => {
                 if let Token::Symbol('[') = t {
-                    let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-
-                    self.tokenizer.advance();
-                    self.compile_expression()?;
+                    self.advance();
+                    index = Some(Box::new(self.parse_expression()?));
 
-                    let t = MatchToken!(self.tokenizer.get_current_token(),
-                                        self.tokenizer.get_line_number(),
-                                        Token::Symbol(']'));
-                    let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+                    MatchToken!(self, self.tokenizer.get_current_token(),
+                                Token::Symbol(']'));
                     
-                    self.tokenizer.advance();
+                    self.advance();
                 }
             },
-            None => return ErrReachedEnd!()
+            None => return ErrReachedEnd!(self, Token::Symbol('['),
+                                          Token::Symbol('='))
         }
  
-        let t = MatchToken!(self.tokenizer.get_current_token(),
-                            self.tokenizer.get_line_number(),
-                            Token::Symbol('='));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-
-        self.tokenizer.advance();
-        self.compile_expression()?;
-
-        let t = MatchToken!(self.tokenizer.get_current_token(),
-                            self.tokenizer.get_line_number(),
-                            Token::Symbol(';'));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+        MatchToken!(self, self.tokenizer.get_current_token(),
+                    Token::Symbol('='));
+
+        self.advance();
+        let value = self.parse_expression()?;
+
+        MatchToken!(self, self.tokenizer.get_current_token(),
+                    Token::Symbol(';'));
  
-        let _ = self.output.write(b"</letStatement>\n");
-        self.tokenizer.advance();
-        Ok(())
+        self.advance();
+        Ok(Statement::Let { name, index, value, span: start.to(&self.prev_span) })
     }
     
     /*
      * 'while' '(' expression ')' '{' statements '}'
      * */
-    fn compile_while(&mut self) -> Result<(), String> {
-        let _ = self.output.write(b"<whileStatement>\n");
- 
-        let t = MatchToken!(self.tokenizer.get_current_token(),
-                            self.tokenizer.get_line_number(),
-                            Token::Keyword(Keyword::While));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-
-        let t = MatchToken!(self.tokenizer.advance(),
-                            self.tokenizer.get_line_number(),
-                            Token::Symbol('('));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-
-        self.tokenizer.advance();
-        self.compile_expression()?;
-
-        let t = MatchToken!(self.tokenizer.get_current_token(),
-                            self.tokenizer.get_line_number(),
-                            Token::Symbol(')'));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-
-        let t = MatchToken!(self.tokenizer.advance(),
-                            self.tokenizer.get_line_number(),
-                            Token::Symbol('{'));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-
-        self.tokenizer.advance();
-        self.compile_statements()?;
-
-        let t = MatchToken!(self.tokenizer.get_current_token(),
-                            self.tokenizer.get_line_number(),
-                            Token::Symbol('}'));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-
-        let _ = self.output.write(b"</whileStatement>\n");
-        self.tokenizer.advance();
-        Ok(())
+    fn parse_while(&mut self) -> Result<Statement, CompileError> {
+        let start = self.span();
+        MatchToken!(self, self.tokenizer.get_current_token(),
+                    Token::Keyword(Keyword::While));
+
+        MatchToken!(self, self.advance(),
+                    Token::Symbol('('));
+
+        self.advance();
+        let condition = self.parse_expression()?;
+
+        MatchToken!(self, self.tokenizer.get_current_token(),
+                    Token::Symbol(')'));
+
+        MatchToken!(self, self.advance(),
+                    Token::Symbol('{'));
+
+        self.advance();
+        let statements = self.parse_statements()?;
+
+        MatchToken!(self, self.tokenizer.get_current_token(),
+                    Token::Symbol('}'));
+
+        self.advance();
+        Ok(Statement::While { condition, statements, span: start.to(&self.prev_span) })
     }
 
     /*
      * 'return' expression? ';'
      * */
-    fn compile_return(&mut self) -> Result<(), String> {
-        let _ = self.output.write(b"<returnStatement>\n");
+    fn parse_return(&mut self) -> Result<Statement, CompileError> {
+        let start = self.span();
+        MatchToken!(self, self.tokenizer.get_current_token(),
+                    Token::Keyword(Keyword::Return));
 
-        let t = MatchToken!(self.tokenizer.get_current_token(),
-                            self.tokenizer.get_line_number(),
-                            Token::Keyword(Keyword::Return));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-
-        match self.tokenizer.advance() {
+        let value = match self.advance() {
             Some(t) => match t {
-                Token::Symbol(';') => {
-                    let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-                },
+                Token::Symbol(';') => None,
                 _ => {
-                    self.compile_expression()?;
+                    let value = self.parse_expression()?;
 
-                    let t = MatchToken!(self.tokenizer.get_current_token(),
-                                        self.tokenizer.get_line_number(),
-                                        Token::Symbol(';'));
-                    let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+                    MatchToken!(self, self.tokenizer.get_current_token(),
+                                Token::Symbol(';'));
+                    Some(value)
                 }
             },
-            None => return ErrReachedEnd!()
-        }
+            None => return ErrReachedEnd!(self)
+        };
         
-        let _ = self.output.write(b"</returnStatement>\n");
-        self.tokenizer.advance();
-        Ok(())
+        self.advance();
+        Ok(Statement::Return { value, span: start.to(&self.prev_span) })
     }
 
     /*
      * 'if' '(' expression ')' '{' statements '}' ('else' '{' statements '}' )?
      * */
-    fn compile_if(&mut self) -> Result<(), String> {
-        let _ = self.output.write(b"<ifStatement>\n");
-
-        let t = MatchToken!(self.tokenizer.get_current_token(),
-                            self.tokenizer.get_line_number(),
-                            Token::Keyword(Keyword::If));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+    fn parse_if(&mut self) -> Result<Statement, CompileError> {
+        let start = self.span();
+        MatchToken!(self, self.tokenizer.get_current_token(),
+                    Token::Keyword(Keyword::If));
 
-        let t = MatchToken!(self.tokenizer.advance(),
-                            self.tokenizer.get_line_number(),
-                            Token::Symbol('('));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+        MatchToken!(self, self.advance(),
+                    Token::Symbol('('));
 
-        self.tokenizer.advance();
-        self.compile_expression()?;
+        self.advance();
+        let condition = self.parse_expression()?;
 
-        let t = MatchToken!(self.tokenizer.get_current_token(),
-                            self.tokenizer.get_line_number(),
-                            Token::Symbol(')'));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+        MatchToken!(self, self.tokenizer.get_current_token(),
+                    Token::Symbol(')'));
 
-        let t = MatchToken!(self.tokenizer.advance(),
-                            self.tokenizer.get_line_number(),
-                            Token::Symbol('{'));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+        MatchToken!(self, self.advance(),
+                    Token::Symbol('{'));
 
-        self.tokenizer.advance();
-        self.compile_statements()?;
+        self.advance();
+        let then_statements = self.parse_statements()?;
 
-        let t = MatchToken!(self.tokenizer.get_current_token(),
-                            self.tokenizer.get_line_number(),
-                            Token::Symbol('}'));
-        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+        MatchToken!(self, self.tokenizer.get_current_token(),
+                    Token::Symbol('}'));
 
-        match self.tokenizer.advance() {
+        let mut else_statements = None;
+        match self.advance() {
             Some(t) => {
                 if let Token::Keyword(Keyword::Else) = t {
-                    let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+                    MatchToken!(self, self.advance(),
+                                Token::Symbol('{'));
 
-                    let t = MatchToken!(self.tokenizer.advance(),
-                                        self.tokenizer.get_line_number(),
-                                        Token::Symbol('{'));
-                    let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+                    self.advance();
+                    else_statements = Some(self.parse_statements()?);
 
-                    self.tokenizer.advance();
-                    self.compile_statements()?;
+                    MatchToken!(self, self.tokenizer.get_current_token(),
+                                Token::Symbol('}'));
 
-                    let t = MatchToken!(self.tokenizer.get_current_token(),
-                                        self.tokenizer.get_line_number(),
-                                        Token::Symbol('}'));
-                    let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-
-                    self.tokenizer.advance();
+                    self.advance();
                 }
             },
-            None => return ErrReachedEnd!()
+            None => return ErrReachedEnd!(self)
         }
 
-        let _ = self.output.write(b"</ifStatement>\n");
-        Ok(())
+        Ok(Statement::If {
+            condition,
+            then_statements,
+            else_statements,
+            span: start.to(&self.prev_span),
+        })
     }
 
     /*
      * term (op term)* 
      * */
-    fn compile_expression(&mut self) -> Result<(), String> {
-        let _ = self.output.write(b"<expression>\n");
-
-        self.compile_term()?;
+    fn parse_expression(&mut self) -> Result<Expression, CompileError> {
+        let start = self.span();
+        let term = self.parse_term()?;
+        let mut rest = Vec::new();
         
         loop {
             match self.tokenizer.get_current_token() {
                 Some(t) => match t {
-                    Token::Symbol('+') | Token::Symbol('-') |
-                    Token::Symbol('*') | Token::Symbol('/') |
-                    Token::Symbol('&') | Token::Symbol('|') |
-                    Token::Symbol('<') | Token::Symbol('>') |
-                    Token::Symbol('=') => {
-                        let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-                        self.tokenizer.advance();
-                        self.compile_term()?;
+                    Token::Symbol(c) => match BinaryOp::from_symbol(*c) {
+                        Some(op) => {
+                            self.advance();
+                            rest.push((op, self.parse_term()?));
+                        },
+                        None => break
                     },
                     _ => break
                 },
-                None => return ErrReachedEnd!()
+                None => return ErrReachedEnd!(self)
             }
         }
 
-        let _ = self.output.write(b"</expression>\n");
-        Ok(())
+        Ok(Expression { term, rest, span: start.to(&self.prev_span) })
     }
 
     /*
@@ -655,10 +848,10 @@ impl<R: Read + Seek, W: Write> CompilationEngine<R, W> {
      * varName '[' expression ']' | subroutineCall | '(' expression ')' |
      * unaryOp term 
      * */
-    fn compile_term(&mut self) -> Result<(), String> {
-        let _ = self.output.write(b"<term>\n");
+    fn parse_term(&mut self) -> Result<Term, CompileError> {
+        let span = self.span();
 
-        match self.tokenizer.get_current_token() {
+        let name = match self.tokenizer.get_current_token() {
             Some(t) => match t {
                 // これらのトークンは先読みが不要なので早期リターンする
                 Token::Keyword(Keyword::True) |
@@ -667,139 +860,176 @@ impl<R: Read + Seek, W: Write> CompilationEngine<R, W> {
                 Token::Keyword(Keyword::This) |
                 Token::Integer(_) |
                 Token::String(_) => {
-                    let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-                    self.tokenizer.advance();
-
-                    let _ = self.output.write(b"</term>\n");
-                    return Ok(())
-                },
-                Token::Identifier(_) => {
-                    let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+                    let term = match t {
+                        // 32768は単項の-の直後でしか使えない
+                        Token::Integer(i) if MAX_INTEGER < *i => {
+                            return Err(CompileError::IntegerOverflow {
+                                value: i.to_string(), span
+                            })
+                        },
+                        Token::Integer(i) => Term::IntegerConstant { value: *i, span },
+                        Token::String(s) => Term::StringConstant { value: s.clone(), span },
+                        Token::Keyword(k) => {
+                            let value = match k {
+                                Keyword::True => KeywordConstant::True,
+                                Keyword::False => KeywordConstant::False,
+                                Keyword::Null => KeywordConstant::Null,
+                                _ => KeywordConstant::This,
+                            };
+                            Term::KeywordConstant { value, span }
+                        },
+                        _ => unreachable!()
+                    };
+                    self.advance();
+                    return Ok(term)
                 },
+                Token::Identifier(_) => self.current_identifier(),
                 // カッコで囲われた式のとき
                 Token::Symbol('(') => {
-                    let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-
-                    self.tokenizer.advance();
-                    self.compile_expression()?;
+                    self.advance();
+                    let expression = Box::new(self.parse_expression()?);
                     
-                    let t = MatchToken!(self.tokenizer.get_current_token(),
-                                        self.tokenizer.get_line_number(),
-                                        Token::Symbol(')'));
-                    let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+                    MatchToken!(self, self.tokenizer.get_current_token(),
+                                Token::Symbol(')'));
+                    self.advance();
+
+                    return Ok(Term::Parenthesized { 
+                        expression, span: span.to(&self.prev_span) 
+                    })
                 },
                 // 単項演算子のとき
                 Token::Symbol('-') | Token::Symbol('~') => {
-                    let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-
-                    self.tokenizer.advance();
-                    self.compile_term()?;
+                    let op = match t {
+                        Token::Symbol('-') => UnaryOp::Neg,
+                        _ => UnaryOp::Not
+                    };
+
+                    // -32768は32768を単項の-で反転したものとして読む
+                    let next = self.advance();
+                    if op == UnaryOp::Neg && next == Some(&Token::Integer(MAX_INTEGER + 1)) {
+                        let term = Box::new(Term::IntegerConstant {
+                            value: MAX_INTEGER + 1, span: self.span()
+                        });
+                        self.advance();
+                        return Ok(Term::Unary { op, term, span: span.to(&self.prev_span) })
+                    }
+                    let term = Box::new(self.parse_term()?);
 
-                    let _ = self.output.write(b"</term>\n");
-                    return Ok(())
+                    return Ok(Term::Unary { op, term, span: span.to(&self.prev_span) })
                 },
-                _ => return ErrUnexpect!(t, self.tokenizer.get_line_number())
+                _ => return ErrUnexpect!(self, t,
+                                         Token::Integer(_),
+                                         Token::String(_),
+                                         Token::Keyword(Keyword::True),
+                                         Token::Keyword(Keyword::False),
+                                         Token::Keyword(Keyword::Null),
+                                         Token::Keyword(Keyword::This),
+                                         Token::Identifier(_),
+                                         Token::Symbol('('),
+                                         Token::Symbol('-'),
+                                         Token::Symbol('~'))
             },
-            None => return ErrReachedEnd!()
-        }
+            None => return ErrReachedEnd!(self)
+        };
 
-        match self.tokenizer.advance() {
+        match self.advance() {
             Some(t) => match t {
                 // 配列のとき
                 Token::Symbol('[') => {
-                    let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+                    self.advance();
+                    let index = Box::new(self.parse_expression()?);
 
-                    self.tokenizer.advance();
-                    self.compile_expression()?;
+                    MatchToken!(self, self.tokenizer.get_current_token(),
+                                Token::Symbol(']'));
 
-                    let t = MatchToken!(self.tokenizer.get_current_token(),
-                                        self.tokenizer.get_line_number(),
-                                        Token::Symbol(']'));
-                    let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-
-                    self.tokenizer.advance();
+                    self.advance();
+                    Ok(Term::ArrayAccess { name, index, span: span.to(&self.prev_span) })
                 },
-                // 関数呼び出しのとき
-                Token::Symbol('(') => {
-                    let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-                    self.compile_expression_list()?;
+                // 関数呼び出しやメソッド呼び出しのとき
+                Token::Symbol('(') | Token::Symbol('.') => {
+                    Ok(Term::SubroutineCall(self.parse_subroutine_call(name)?))
+                },
+                // それ以外はただの変数として扱う
+                _ => Ok(Term::VarName(name))
+            },
+            None => ErrReachedEnd!(self)
+        }
+    }
 
-                    let t = MatchToken!(self.tokenizer.get_current_token(),
-                                        self.tokenizer.get_line_number(),
-                                        Token::Symbol(')'));
-                    let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+    /*
+     * 最初の識別子は読み終えていて、現在のトークンは'('か'.'とする
+     * subroutineName '(' expressionList ')' |
+     * (className | varName) '.' subroutineName '(' expressionList ')'
+     * */
+    fn parse_subroutine_call(&mut self, name: Identifier) 
+        -> Result<SubroutineCall, CompileError> {
+        let start = name.span.clone();
 
-                    self.tokenizer.advance();
-                },
+        let (receiver, name) = match self.tokenizer.get_current_token() {
+            Some(t) => match t {
+                // 関数呼び出しのとき
+                Token::Symbol('(') => (None, name),
                 // メソッド呼び出しのとき
                 Token::Symbol('.') => {
-                    let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-
-                    let t = MatchToken!(self.tokenizer.advance(),
-                                        self.tokenizer.get_line_number(),
-                                        Token::Identifier(_));
-                    let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-
-                    let t = MatchToken!(self.tokenizer.advance(),
-                                        self.tokenizer.get_line_number(),
-                                        Token::Symbol('('));
-                    let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-
-                    self.compile_expression_list()?;
-
-                    let t = MatchToken!(self.tokenizer.get_current_token(),
-                                        self.tokenizer.get_line_number(),
-                                        Token::Symbol(')'));
-                    let _ = self.output.write((t.to_xml() + "\n").as_bytes());
+                    MatchToken!(self, self.advance(),
+                                Token::Identifier(_));
+                    let subroutine_name = self.current_identifier();
 
-                    self.tokenizer.advance();
+                    MatchToken!(self, self.advance(),
+                                Token::Symbol('('));
+                    (Some(name), subroutine_name)
                 },
-                // それ以外はただの変数として扱う
-                _ => {
-                    // すでにxmlに出力しているので特にすることはない
-                }
+                _ => return ErrUnexpect!(self, t,
+                                         Token::Symbol('('),
+                                         Token::Symbol('.'))
             },
-            None => return ErrReachedEnd!()
-        }
+            None => return ErrReachedEnd!(self, Token::Symbol('('),
+                                          Token::Symbol('.'))
+        };
 
-        let _ = self.output.write(b"</term>\n");
-        Ok(())
+        let arguments = self.parse_expression_list()?;
+
+        MatchToken!(self, self.tokenizer.get_current_token(),
+                    Token::Symbol(')'));
+
+        self.advance();
+        Ok(SubroutineCall { receiver, name, arguments, span: start.to(&self.prev_span) })
     }
 
     /*
      * (expression (',' expression)* )?
      * */
-    fn compile_expression_list(&mut self) -> Result<(), String> {
-        let _ = self.output.write(b"<expressionList>\n");
+    fn parse_expression_list(&mut self) -> Result<Vec<Expression>, CompileError> {
+        let mut expressions = Vec::new();
 
         loop {
-            match self.tokenizer.advance() {
+            match self.advance() {
                 Some(t) => match t {
                     Token::Symbol(')') => {
                         break
                     },
                     _ => {
-                        self.compile_expression()?;
+                        expressions.push(self.parse_expression()?);
                         match self.tokenizer.get_current_token() {
                             Some(t) => match t {
                                 Token::Symbol(')') => {
                                     break
                                 },
-                                Token::Symbol(',') => {
-                                    let _ = self.output.write((t.to_xml() + "\n").as_bytes());
-                                },
-                                _ => return ErrUnexpect!(t, self.tokenizer.get_line_number())
+                                Token::Symbol(',') => (),
+                                _ => return ErrUnexpect!(self, t,
+                                                         Token::Symbol(')'),
+                                                         Token::Symbol(','))
                             },
-                            None => return ErrReachedEnd!()
+                            None => return ErrReachedEnd!(self, Token::Symbol(')'),
+                                                          Token::Symbol(','))
                         }
                     }
                 },
-                None => return ErrReachedEnd!()
+                None => return ErrReachedEnd!(self)
             }
         }
 
-        let _ = self.output.write(b"</expressionList>\n");
-        Ok(())
+        Ok(expressions)
     }
 }
 
@@ -807,12 +1037,22 @@ impl<R: Read + Seek, W: Write> CompilationEngine<R, W> {
 mod test {
     use std::io::Cursor;
     use super::CompilationEngine;
+    use super::Target;
     use super::Tokenizer;
+    use super::Span;
+    use super::CompileError;
+    use super::expected_name;
+    use crate::ast::*;
+
+    // 範囲の始まりと終わりの行番号と列番号を返す
+    fn position(span: &Span) -> (usize, usize, usize, usize) {
+        (span.start_line, span.start_col, span.end_line, span.end_col)
+    }
 
     #[test]
     fn test_compilation_engine_compile_class() {
         let t = Tokenizer::new(Cursor::new("class test{}"));
-        let mut c = CompilationEngine::new(t, Cursor::new(Vec::new()));
+        let mut c = CompilationEngine::new(t, Cursor::new(Vec::new()), Target::Xml);
         c.tokenizer.advance();
         assert_eq!(c.compile_class(), Ok(()));
 
@@ -821,7 +1061,266 @@ mod test {
                    "<class><keyword>class</keyword><identifier>test</identifier><symbol>{</symbol><symbol>}</symbol></class>");
 
         let t = Tokenizer::new(Cursor::new("test{"));
-        let mut c = CompilationEngine::new(t, Cursor::new(Vec::new()));
+        let mut c = CompilationEngine::new(t, Cursor::new(Vec::new()), Target::Xml);
         assert_ne!(c.compile_class(), Ok(()));
     }
+
+    #[test]
+    fn test_compilation_engine_compile_class_error() {
+        let t = Tokenizer::with_file(Cursor::new("class A {\n field int x y;\n}"),
+                                     "A.jack");
+        let mut c = CompilationEngine::new(t, Cursor::new(Vec::new()), Target::Xml);
+        c.tokenizer.advance();
+        match c.compile_class().unwrap_err().as_slice() {
+            [CompileError::UnexpectedToken { found, expected, span }] => {
+                assert_eq!(found, "y");
+                assert_eq!(expected, &vec![";", ","]);
+                assert_eq!(span.to_string(), "A.jack:2:14");
+            },
+            r => panic!("{:?}", r)
+        }
+
+        let t = Tokenizer::new(Cursor::new("class A {"));
+        let mut c = CompilationEngine::new(t, Cursor::new(Vec::new()), Target::Xml);
+        c.tokenizer.advance();
+        match c.compile_class().unwrap_err().as_slice() {
+            [CompileError::UnexpectedEof { .. }] => (),
+            r => panic!("{:?}", r)
+        }
+
+        // 字句解析のエラーはUnexpectedEofより優先される
+        let t = Tokenizer::new(Cursor::new("class A { /* "));
+        let mut c = CompilationEngine::new(t, Cursor::new(Vec::new()), Target::Xml);
+        c.tokenizer.advance();
+        match c.compile_class().unwrap_err().as_slice() {
+            [CompileError::UnterminatedComment { span }] => {
+                assert_eq!(position(span), (1, 11, 1, 14));
+            },
+            r => panic!("{:?}", r)
+        }
+
+        // 改行を含む文字列も文字列の始まりを指すエラーになる。
+        // 次の行の"も閉じていない文字列になる
+        let t = Tokenizer::new(Cursor::new(
+            "class A {\n function void f() {\n  do g(\"a\n\");\n }\n}"));
+        let mut c = CompilationEngine::new(t, Cursor::new(Vec::new()), Target::Xml);
+        c.tokenizer.advance();
+        match c.compile_class().unwrap_err().as_slice() {
+            [CompileError::UnterminatedString { span }, ..] => {
+                assert_eq!(position(span), (3, 8, 3, 10));
+            },
+            r => panic!("{:?}", r)
+        }
+    }
+
+    #[test]
+    fn test_compilation_engine_compile_class_recovery() {
+        let source = r#"class A {
+            field int x y;
+            function void f() {
+                let x = ;
+                do g(;
+                let x = 1;
+                return
+            }
+            method void g() {
+                return;
+            }
+            function int h( {
+                return 1;
+            }
+        }"#;
+
+        let t = Tokenizer::new(Cursor::new(source));
+        let mut c = CompilationEngine::new(t, Cursor::new(Vec::new()), Target::Vm);
+        c.tokenizer.advance();
+        let errors = c.compile_class().unwrap_err();
+        let lines: Vec<(String, usize)> = errors.iter()
+            .map(|e| (e.message(), e.span().unwrap().start_line))
+            .collect();
+        assert_eq!(lines, vec![
+            ("unexpected token: 'y'".to_string(), 2),
+            ("unexpected token: ';'".to_string(), 4),
+            ("unexpected token: ';'".to_string(), 5),
+            ("unexpected token: '}'".to_string(), 8),
+            ("unexpected token: '{'".to_string(), 12),
+        ]);
+        // エラーがあるときは何も書き込まない
+        assert!(c.output.get_ref().is_empty());
+
+        // エラーの数が上限に達したら解析をやめる
+        let t = Tokenizer::new(Cursor::new(source));
+        let mut c = CompilationEngine::new(t, Cursor::new(Vec::new()), Target::Vm);
+        c.set_max_errors(2);
+        c.tokenizer.advance();
+        assert_eq!(c.compile_class().unwrap_err().len(), 2);
+
+        // 上限が0でもエラーを1つは返す
+        let t = Tokenizer::new(Cursor::new("class A { function void f() { let y = 1; return; } }"));
+        let mut c = CompilationEngine::new(t, Cursor::new(Vec::new()), Target::Vm);
+        c.set_max_errors(0);
+        c.tokenizer.advance();
+        assert_eq!(c.compile_class().unwrap_err().len(), 1);
+    }
+
+    #[test]
+    fn test_compilation_engine_compile_class_recovery_lexical() {
+        // 字句解析のエラーの後や変数の宣言の中のエラーの後も解析を続け、
+        // クラスの後のトークンもエラーにする
+        let source = r#"class A {
+            function void f() {
+                var int a b;
+                var int c;
+                let c = @ 1;
+                let c = 40000;
+                return;
+            }
+        } extra"#;
+
+        let t = Tokenizer::new(Cursor::new(source));
+        let mut c = CompilationEngine::new(t, Cursor::new(Vec::new()), Target::Vm);
+        c.tokenizer.advance();
+        let errors = c.compile_class().unwrap_err();
+        let lines: Vec<(String, usize)> = errors.iter()
+            .map(|e| (e.message(), e.span().unwrap().start_line))
+            .collect();
+        assert_eq!(lines, vec![
+            ("unexpected token: 'b'".to_string(), 3),
+            ("unknown start of token: @".to_string(), 5),
+            ("integer number too large: 40000".to_string(), 6),
+            ("unexpected token: 'extra'".to_string(), 9),
+        ]);
+    }
+
+    #[test]
+    fn test_expected_name() {
+        assert_eq!(expected_name("Token::Symbol('{')"), "{");
+        assert_eq!(expected_name("Token :: Keyword(Keyword :: Class)"), "class");
+        assert_eq!(expected_name("Token::Identifier(_)"), "identifier");
+        assert_eq!(expected_name("Token::Integer(_)"), "integer constant");
+    }
+
+    #[test]
+    fn test_compilation_engine_compile_class_vm() {
+        let t = Tokenizer::new(Cursor::new(r#"
+        class Main {
+            function void main() {
+                do Output.printInt(1 + (2 * 3));
+                return;
+            }
+        }"#));
+        let mut c = CompilationEngine::new(t, Cursor::new(Vec::new()), Target::Vm);
+        c.tokenizer.advance();
+        assert_eq!(c.compile_class(), Ok(()));
+
+        let s = String::from_utf8(c.output.into_inner()).unwrap();
+        assert_eq!(s, "function Main.main 0\npush constant 1\npush constant 2\n\
+                       push constant 3\ncall Math.multiply 2\nadd\n\
+                       call Output.printInt 1\npop temp 0\n\
+                       push constant 0\nreturn\n");
+
+        let source = r#"
+        class Point {
+            field int x;
+            method int getX() { return x; }
+            function void f() { let y = 1; return; }
+        }"#;
+        let t = Tokenizer::new(Cursor::new(source));
+        let mut c = CompilationEngine::new(t, Cursor::new(Vec::new()), Target::Vm);
+        c.tokenizer.advance();
+        // 宣言されていない変数への代入はエラーになる
+        match c.compile_class().unwrap_err().as_slice() {
+            [CompileError::UndefinedVariable { name, span }] => {
+                assert_eq!(name, "y");
+                assert_eq!(position(span), (5, 37, 5, 38));
+            },
+            r => panic!("{:?}", r)
+        }
+
+        // エラーがあるときは何も書き込まない
+        assert!(c.output.into_inner().is_empty());
+
+        // 構文木は変数を解決せずに書く
+        let t = Tokenizer::new(Cursor::new(source));
+        let mut c = CompilationEngine::new(t, Cursor::new(Vec::new()), Target::Xml);
+        c.tokenizer.advance();
+        assert_eq!(c.compile_class(), Ok(()));
+        assert!(!c.output.into_inner().is_empty());
+    }
+
+    #[test]
+    fn test_compilation_engine_integer_range() {
+        // -32768は32767のビット反転として書き出す
+        let t = Tokenizer::new(Cursor::new(
+            "class A { function int f() { return -32768 - -1; } }"));
+        let mut c = CompilationEngine::new(t, Cursor::new(Vec::new()), Target::Vm);
+        c.tokenizer.advance();
+        assert_eq!(c.compile_class(), Ok(()));
+        let s = String::from_utf8(c.output.into_inner()).unwrap();
+        assert_eq!(s, "function A.f 0\npush constant 32767\nnot\n\
+                       push constant 1\nneg\nsub\nreturn\n");
+
+        // 単項の-の直後以外の32768はエラーになる
+        let t = Tokenizer::new(Cursor::new(
+            "class A { function int f() { return 1 - 32768; } }"));
+        let mut c = CompilationEngine::new(t, Cursor::new(Vec::new()), Target::Vm);
+        c.tokenizer.advance();
+        match c.compile_class().unwrap_err().as_slice() {
+            [CompileError::IntegerOverflow { value, span }] => {
+                assert_eq!(value, "32768");
+                assert_eq!(position(span), (1, 41, 1, 46));
+            },
+            r => panic!("{:?}", r)
+        }
+    }
+
+    #[test]
+    fn test_compilation_engine_parse_class() {
+        let t = Tokenizer::new(Cursor::new(r#"class A {
+            field int x, y;
+            method void f(int a) {
+                let x = -a;
+                return;
+            }
+        }"#));
+        let mut c = CompilationEngine::new(t, Cursor::new(Vec::new()), Target::Xml);
+        c.tokenizer.advance();
+        let class = c.parse_class().unwrap();
+
+        assert_eq!(class.name.name, "A");
+        assert_eq!(position(&class.span), (1, 1, 7, 10));
+        assert_eq!(class.class_var_decs[0].kind, ClassVarKind::Field);
+        assert_eq!(class.class_var_decs[0].names.len(), 2);
+
+        let f = &class.subroutine_decs[0];
+        assert_eq!(f.kind, SubroutineKind::Method);
+        assert_eq!(f.return_type, None);
+        assert_eq!(f.parameters[0].ty, Type::Int);
+        assert_eq!(position(&f.span), (3, 13, 6, 14));
+
+        match &f.statements[0] {
+            Statement::Let { name, index: None, value, span } => {
+                assert_eq!(name.name, "x");
+                assert_eq!(position(span), (4, 17, 4, 28));
+                match &value.term {
+                    Term::Unary { op: UnaryOp::Neg, term, .. } => match &**term {
+                        Term::VarName(a) => {
+                            assert_eq!(a.name, "a");
+                            assert_eq!(position(&a.span), (4, 26, 4, 27));
+                            assert_eq!(a.span.byte_range.len(), 1);
+                        },
+                        t => panic!("{:?}", t)
+                    },
+                    t => panic!("{:?}", t)
+                }
+            },
+            s => panic!("{:?}", s)
+        }
+        match &f.statements[1] {
+            Statement::Return { value: None, span } => {
+                assert_eq!(position(span), (5, 17, 5, 24));
+            },
+            s => panic!("{:?}", s)
+        }
+    }
 }
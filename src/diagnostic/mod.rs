@@ -0,0 +1,250 @@
+//! エラーをソースコードの該当行と一緒に表示する。
+//! 出力はrustcのエラー表示に似せている
+//!
+//! error[E0001]: unexpected token: 'y'
+//!  --> Main.jack:2:14
+//!   |
+//! 2 |  field int x y;
+//!   |              ^
+//!   = note: expected one of: `;`, `,`
+
+use std::fmt;
+
+use super::error::CompileError;
+use super::message::{Code, Lang};
+
+
+/// 診断の重さ。警告はコンパイルを止めない
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    /// langの言語にした名前を返す
+    pub fn label(self, lang: Lang) -> &'static str {
+        match self {
+            Severity::Error => Code::Error.template(lang),
+            Severity::Warning => Code::Warning.template(lang),
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.label(Lang::En))
+    }
+}
+
+/// エラーと、それをソースコードと一緒に表示するための文字列の組
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub error: CompileError,
+    /// render_asで作った表示用の文字列
+    pub rendered: String,
+}
+
+impl Diagnostic {
+    /// sourceはエラーが起きたファイルの中身
+    pub fn new(error: CompileError, source: &str) -> Diagnostic {
+        Diagnostic::with_lang(Severity::Error, error, source, Lang::En)
+    }
+
+    /// 警告の診断を作る
+    pub fn warning(error: CompileError, source: &str) -> Diagnostic {
+        Diagnostic::with_lang(Severity::Warning, error, source, Lang::En)
+    }
+
+    /// 重さと表示する言語を指定して診断を作る
+    pub fn with_lang(severity: Severity, error: CompileError, source: &str, lang: Lang)
+        -> Diagnostic {
+        let rendered = render_as(severity, &error, source, lang);
+        Diagnostic { severity, error, rendered }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.rendered)
+    }
+}
+
+// 受け付けるトークンの一覧をnoteの文にする
+fn expected_note(expected: &[String], lang: Lang) -> Option<String> {
+    let names: Vec<String> = expected.iter().map(|e| format!("`{}`", e)).collect();
+    match names.len() {
+        0 => None,
+        1 => Some(Code::ExpectedOne.format(lang, &[&names[0]])),
+        _ => Some(Code::ExpectedOneOf.format(lang, &[&names.join(", ")])),
+    }
+}
+
+// 行のstart_colからend_colまでの文字を返す。列番号は文字数で数える
+fn columns(line: &str, start_col: usize, end_col: usize) -> impl Iterator<Item = char> + '_ {
+    let start = start_col.saturating_sub(1);
+    line.chars().skip(start).take(end_col.saturating_sub(1).saturating_sub(start))
+}
+
+// 端末に表示したときの文字の幅。漢字やかなのような全角の文字は2とする
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        0x1100..=0x115f | 0x2e80..=0x303e | 0x3041..=0x33ff | 0x3400..=0x4dbf |
+        0x4e00..=0x9fff | 0xa000..=0xa4cf | 0xac00..=0xd7a3 | 0xf900..=0xfaff |
+        0xfe30..=0xfe4f | 0xff00..=0xff60 | 0xffe0..=0xffe6 |
+        0x1f300..=0x1f64f | 0x1f900..=0x1f9ff | 0x20000..=0x3fffd => 2,
+        _ => 1,
+    }
+}
+
+// 下線を引く前の空白を作る。タブはそのまま残し、全角の文字は2つの空白にして
+// 位置を合わせる
+fn padding(line: &str, col: usize) -> String {
+    columns(line, 1, col)
+        .map(|c| if c == '\t' { "\t".to_string() } else { " ".repeat(char_width(c)) })
+        .collect()
+}
+
+/// エラーを表示用の文字列にする。sourceはエラーが起きたファイルの中身
+pub fn render(error: &CompileError, source: &str) -> String {
+    render_as(Severity::Error, error, source, Lang::En)
+}
+
+/// 重さと言語を指定して表示用の文字列にする。
+/// error[E0001]:のように重さとエラーの番号から始まる
+pub fn render_as(severity: Severity, error: &CompileError, source: &str, lang: Lang)
+    -> String {
+    let mut s = format!("{}[{}]: {}\n",
+                        severity.label(lang), error.code().id(), error.message_in(lang));
+
+    let span = match error.span() {
+        Some(span) => span,
+        // 位置のないエラーはメッセージだけ
+        None => return s
+    };
+
+    // 行番号の幅に合わせて左側の余白を決める
+    let width = span.start_line.to_string().len();
+    let gutter = " ".repeat(width);
+    s += &format!("{}--> {}\n", gutter, span);
+
+    if let Some(line) = source.lines().nth(span.start_line.saturating_sub(1)) {
+        // 複数行にまたがる場合は最初の行の終わりまで下線を引く
+        let end_col = if span.start_line == span.end_line {
+            span.end_col
+        } else {
+            line.chars().count() + 1
+        };
+        // 全角の文字には^を2つ引く
+        let len: usize = columns(line, span.start_col, end_col).map(char_width).sum();
+        let carets = "^".repeat(len.max(1));
+
+        s += &format!("{} |\n", gutter);
+        s += &format!("{} | {}\n", span.start_line, line);
+        s += &format!("{} | {}{}\n", gutter, padding(line, span.start_col), carets);
+    }
+
+    if let Some(note) = expected_note(error.expected(), lang) {
+        s += &format!("{} = {}: {}\n", gutter, Code::Note.template(lang), note);
+    }
+    if let Some(suggestion) = error.suggestion() {
+        s += &format!("{} = {}: {}\n", gutter, Code::Hint.template(lang),
+                      Code::DidYouMean.format(lang, &[suggestion]));
+    }
+
+    s
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::render;
+    use super::render_as;
+    use super::Severity;
+    use crate::error::CompileError;
+    use crate::message::Lang;
+    use crate::span::Span;
+
+    #[test]
+    fn test_render() {
+        let source = "class A {\n\tfield int x yz;\n}\n";
+        let e = CompileError::UnexpectedToken {
+            found: "yz".to_string(),
+            expected: vec![";".to_string(), ",".to_string()],
+            span: Span {
+                file: Some("A.jack".into()),
+                start_line: 2, start_col: 14, end_line: 2, end_col: 16,
+                byte_range: 23..25,
+            },
+        };
+        assert_eq!(render(&e, source), "error[E0001]: unexpected token: 'yz'\n \
+                                        --> A.jack:2:14\n  \
+                                        |\n\
+                                        2 | \tfield int x yz;\n  \
+                                        | \t            ^^\n  \
+                                        = note: expected one of: `;`, `,`\n");
+
+        // 幅0の範囲でも^を1つ書く
+        let e = CompileError::UnexpectedEof {
+            expected: vec!["}".to_string()],
+            span: Span {
+                start_line: 3, start_col: 2, end_line: 3, end_col: 2,
+                ..Span::default()
+            },
+        };
+        assert_eq!(render(&e, source), "error[E0002]: reached end of file while parsing\n \
+                                        --> 3:2\n  |\n3 | }\n  |  ^\n  \
+                                        = note: expected `}`\n");
+
+        // 列番号は文字数で数え、全角の文字の下は空白2つで揃える
+        let e = CompileError::UnexpectedToken {
+            found: "x".to_string(),
+            expected: Vec::new(),
+            span: Span {
+                start_line: 1, start_col: 14, end_line: 1, end_col: 15,
+                ..Span::default()
+            },
+        };
+        assert_eq!(render(&e, "let s = \"あい\" x;"),
+                   "error[E0001]: unexpected token: 'x'\n --> 1:14\n  |\n\
+                    1 | let s = \"あい\" x;\n  |                ^\n");
+
+        assert_eq!(Severity::Warning.to_string(), "warning");
+
+        // 警告はwarning:で始める
+        assert!(render_as(Severity::Warning, &e, "", Lang::En)
+            .starts_with("warning[E0001]: unexpected token"));
+
+        // 全角の文字の下線は^を2つにする
+        let wide = CompileError::UnknownCharacter {
+            found: 'あ',
+            span: Span { start_line: 1, start_col: 3, end_line: 1, end_col: 4, ..Span::default() },
+        };
+        assert_eq!(render(&wide, "x あ"),
+                   "error[E0006]: unknown start of token: あ\n --> 1:3\n  |\n1 | x あ\n  |   ^^\n");
+
+        // 日本語で表示する
+        let e = CompileError::UnexpectedEof {
+            expected: vec!["}".to_string()],
+            span: Span { start_line: 1, start_col: 1, end_line: 1, end_col: 1, ..Span::default() },
+        };
+        assert_eq!(render_as(Severity::Error, &e, "", Lang::Ja),
+                   "エラー[E0002]: 構文の途中でファイルが終わりました\n --> 1:1\n  = 注: `}`が必要です\n");
+    }
+
+    #[test]
+    fn test_render_suggestion() {
+        let e = CompileError::UnknownSubroutine {
+            name: "Output.printint".to_string(),
+            suggestion: Some("printInt".to_string()),
+            span: Span { start_line: 1, start_col: 11, end_line: 1, end_col: 19, ..Span::default() },
+        };
+        assert_eq!(render(&e, "do Output.printint(1);"),
+                   "error[E0017]: unknown subroutine: 'Output.printint'\n --> 1:11\n  |\n\
+                    1 | do Output.printint(1);\n  |           ^^^^^^^^\n  \
+                    = help: did you mean `printInt`?\n");
+        assert!(render_as(Severity::Error, &e, "", Lang::Ja)
+            .ends_with("  = ヒント: `printInt`のことですか?\n"));
+    }
+}
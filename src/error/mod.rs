@@ -0,0 +1,324 @@
+//! コンパイル中に起きるエラーの型。
+//! エラーの種類ごとにvariantを分け、位置はSpanで持つ
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use super::span::Span;
+use super::ast::SubroutineKind;
+use super::message::{Code, Lang};
+use super::vm_translator::TranslateError;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompileError {
+    /// 予期しないトークン。expectedにはその位置で受け付けるトークンが入る
+    UnexpectedToken {
+        found: String,
+        expected: Vec<String>,
+        span: Span,
+    },
+    /// 構文の途中でファイルの終わりに達した
+    UnexpectedEof {
+        expected: Vec<String>,
+        span: Span,
+    },
+    /// 閉じていないか途中で改行している文字列。spanは文字列の始まりから
+    /// 行の終わりまで
+    UnterminatedString {
+        span: Span,
+    },
+    /// 閉じていないブロックコメント。spanはコメントの始まり
+    UnterminatedComment {
+        span: Span,
+    },
+    /// UTF-8として正しくないバイト
+    InvalidUtf8 {
+        span: Span,
+    },
+    /// トークンの始まりにならない文字
+    UnknownCharacter {
+        found: char,
+        span: Span,
+    },
+    /// 0から32767の範囲にない整数
+    IntegerOverflow {
+        value: String,
+        span: Span,
+    },
+    /// 宣言されていない変数
+    UndefinedVariable {
+        name: String,
+        span: Span,
+    },
+    /// 関数の中でフィールドを参照した。関数にはthisがないので参照できない
+    FieldInFunction {
+        name: String,
+        span: Span,
+    },
+    /// 同じスコープで宣言済みの名前をもう一度宣言した。previousは最初の宣言
+    Redeclared {
+        name: String,
+        span: Span,
+        previous: Box<Span>,
+    },
+    /// サブルーチンの変数や引数がクラスの変数を隠している。previousはクラスの変数の宣言
+    Shadowed {
+        name: String,
+        span: Span,
+        previous: Box<Span>,
+    },
+    /// 型が合わない。--strict-typesのときだけ報告する
+    TypeMismatch {
+        expected: String,
+        found: String,
+        span: Span,
+    },
+    /// Arrayでない変数を添字で参照した
+    NotAnArray {
+        name: String,
+        ty: String,
+        span: Span,
+    },
+    /// int、char、booleanの変数のメソッドを呼び出した
+    MethodOnPrimitive {
+        name: String,
+        ty: String,
+        span: Span,
+    },
+    /// 呼び出したクラスにないサブルーチン。nameはクラス名.サブルーチン名。
+    /// suggestionは似た名前のサブルーチン
+    UnknownSubroutine {
+        name: String,
+        suggestion: Option<String>,
+        span: Span,
+    },
+    /// 引数の数が宣言と違う
+    ArgumentCount {
+        name: String,
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+    /// メソッドをクラス名で呼び出したか、関数やコンストラクタをメソッドとして
+    /// 呼び出した。declaredは宣言したときの種類
+    KindMismatch {
+        name: String,
+        declared: SubroutineKind,
+        span: Span,
+    },
+    /// vmコードをアセンブリに変換できない。lineは1から始まる
+    InvalidVm {
+        line: usize,
+        message: String,
+    },
+    /// プログラムのどのクラスにも定義されていない関数を呼び出している
+    UndefinedFunction {
+        name: String,
+    },
+    /// ファイルの読み書きのエラー
+    Io {
+        path: String,
+        kind: io::ErrorKind,
+        message: String,
+    },
+}
+
+impl CompileError {
+    /// 入出力のエラーをpathと一緒に保持する
+    pub fn io(path: &str, e: &io::Error) -> CompileError {
+        CompileError::Io {
+            path: path.to_string(),
+            kind: e.kind(),
+            message: e.to_string(),
+        }
+    }
+
+    /// エラーの位置を返す。位置のないエラーはNoneを返す
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            CompileError::UnexpectedToken { span, .. } |
+            CompileError::UnexpectedEof { span, .. } |
+            CompileError::UnterminatedString { span } |
+            CompileError::UnterminatedComment { span } |
+            CompileError::InvalidUtf8 { span } |
+            CompileError::UnknownCharacter { span, .. } |
+            CompileError::IntegerOverflow { span, .. } |
+            CompileError::UndefinedVariable { span, .. } |
+            CompileError::FieldInFunction { span, .. } |
+            CompileError::Redeclared { span, .. } |
+            CompileError::Shadowed { span, .. } |
+            CompileError::TypeMismatch { span, .. } |
+            CompileError::NotAnArray { span, .. } |
+            CompileError::MethodOnPrimitive { span, .. } |
+            CompileError::UnknownSubroutine { span, .. } |
+            CompileError::ArgumentCount { span, .. } |
+            CompileError::KindMismatch { span, .. } => Some(span),
+            CompileError::InvalidVm { .. } |
+            CompileError::UndefinedFunction { .. } |
+            CompileError::Io { .. } => None,
+        }
+    }
+
+    /// エラーの種類を返す
+    pub fn code(&self) -> Code {
+        match self {
+            CompileError::UnexpectedToken { .. } => Code::UnexpectedToken,
+            CompileError::UnexpectedEof { .. } => Code::UnexpectedEof,
+            CompileError::UnterminatedString { .. } => Code::UnterminatedString,
+            CompileError::UnterminatedComment { .. } => Code::UnterminatedComment,
+            CompileError::InvalidUtf8 { .. } => Code::InvalidUtf8,
+            CompileError::UnknownCharacter { .. } => Code::UnknownCharacter,
+            CompileError::IntegerOverflow { .. } => Code::IntegerOverflow,
+            CompileError::UndefinedVariable { .. } => Code::UndefinedVariable,
+            CompileError::Redeclared { .. } => Code::Redeclared,
+            CompileError::Shadowed { .. } => Code::Shadowed,
+            CompileError::TypeMismatch { .. } => Code::TypeMismatch,
+            CompileError::NotAnArray { .. } => Code::NotAnArray,
+            CompileError::MethodOnPrimitive { .. } => Code::MethodOnPrimitive,
+            CompileError::UnknownSubroutine { .. } => Code::UnknownSubroutine,
+            CompileError::ArgumentCount { .. } => Code::ArgumentCount,
+            CompileError::KindMismatch { declared: SubroutineKind::Method, .. } => {
+                Code::MethodCalledAsFunction
+            },
+            CompileError::KindMismatch { .. } => Code::FunctionCalledAsMethod,
+            CompileError::InvalidVm { .. } => Code::InvalidVm,
+            CompileError::UndefinedFunction { .. } => Code::UndefinedFunction,
+            CompileError::FieldInFunction { .. } => Code::FieldInFunction,
+            CompileError::Io { .. } => Code::Io,
+        }
+    }
+
+    /// 位置を含まないエラーの説明を英語で返す
+    pub fn message(&self) -> String {
+        self.message_in(Lang::En)
+    }
+
+    /// 位置を含まないエラーの説明をlangの言語で返す
+    pub fn message_in(&self, lang: Lang) -> String {
+        match self {
+            CompileError::UnexpectedToken { found, .. } => {
+                self.code().format(lang, &[found])
+            },
+            CompileError::UnknownCharacter { found, .. } => {
+                self.code().format(lang, &[&found.to_string()])
+            },
+            CompileError::IntegerOverflow { value, .. } => self.code().format(lang, &[value]),
+            CompileError::UndefinedVariable { name, .. } |
+            CompileError::FieldInFunction { name, .. } => self.code().format(lang, &[name]),
+            CompileError::Redeclared { name, previous, .. } |
+            CompileError::Shadowed { name, previous, .. } => {
+                self.code().format(lang, &[name, &previous.to_string()])
+            },
+            CompileError::TypeMismatch { expected, found, .. } => {
+                self.code().format(lang, &[expected, found])
+            },
+            CompileError::NotAnArray { name, ty, .. } |
+            CompileError::MethodOnPrimitive { name, ty, .. } => {
+                self.code().format(lang, &[name, ty])
+            },
+            CompileError::UnknownSubroutine { name, .. } => self.code().format(lang, &[name]),
+            CompileError::ArgumentCount { name, expected, found, .. } => {
+                self.code().format(lang, &[name, &expected.to_string(), &found.to_string()])
+            },
+            CompileError::KindMismatch { name, declared, .. } => {
+                self.code().format(lang, &[name, declared.keyword()])
+            },
+            CompileError::InvalidVm { line, message } => {
+                self.code().format(lang, &[&line.to_string(), message])
+            },
+            CompileError::UndefinedFunction { name } => self.code().format(lang, &[name]),
+            // よくあるエラーは訳し、それ以外はOSの説明をそのまま使う
+            CompileError::Io { path, kind, message } => {
+                let message = match kind {
+                    io::ErrorKind::NotFound => Code::IoNotFound.format(lang, &[]),
+                    io::ErrorKind::PermissionDenied => {
+                        Code::IoPermissionDenied.format(lang, &[])
+                    },
+                    _ => message.clone(),
+                };
+                self.code().format(lang, &[path, &message])
+            },
+            _ => self.code().format(lang, &[]),
+        }
+    }
+
+    /// その位置で受け付けるトークンの一覧を返す
+    pub fn expected(&self) -> &[String] {
+        match self {
+            CompileError::UnexpectedToken { expected, .. } |
+            CompileError::UnexpectedEof { expected, .. } => expected,
+            _ => &[]
+        }
+    }
+
+    /// 似た名前の候補を返す
+    pub fn suggestion(&self) -> Option<&str> {
+        match self {
+            CompileError::UnknownSubroutine { suggestion, .. } => suggestion.as_deref(),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // 位置がわかるときは先頭に書く
+        if let Some(span) = self.span() {
+            write!(f, "{}: ", span)?;
+        }
+        write!(f, "{}", self.message())
+    }
+}
+
+impl Error for CompileError {}
+
+impl From<TranslateError> for CompileError {
+    fn from(e: TranslateError) -> CompileError {
+        match e {
+            TranslateError::InvalidLine { line, message } => {
+                CompileError::InvalidVm { line, message }
+            },
+            TranslateError::UndefinedFunction { name } => {
+                CompileError::UndefinedFunction { name }
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io;
+
+    use super::CompileError;
+    use crate::message::Lang;
+    use crate::span::Span;
+
+    #[test]
+    fn test_compile_error_display() {
+        let span = Span {
+            file: Some("Main.jack".into()), start_line: 3, start_col: 5, ..Span::default()
+        };
+        let e = CompileError::UnexpectedToken {
+            found: "}".to_string(),
+            expected: vec![";".to_string()],
+            span,
+        };
+        assert_eq!(e.to_string(), "Main.jack:3:5: unexpected token: '}'");
+
+        let span = Span { start_line: 1, start_col: 1, ..Span::default() };
+        let e = CompileError::UnexpectedEof { expected: Vec::new(), span };
+        assert_eq!(e.to_string(), "1:1: reached end of file while parsing");
+
+        let e = CompileError::io("A.jack", &io::Error::new(io::ErrorKind::NotFound, "not found"));
+        assert_eq!(e.span(), None);
+        assert_eq!(e.to_string(), "A.jack: file not found");
+        assert_eq!(e.message_in(Lang::Ja), "A.jack: ファイルが見つかりません");
+
+        // 訳のないエラーはOSの説明を使う
+        let e = CompileError::io("A.jack", &io::Error::other("disk full"));
+        assert_eq!(e.message_in(Lang::Ja), "A.jack: disk full");
+    }
+}
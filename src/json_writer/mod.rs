@@ -0,0 +1,306 @@
+//! 構文木をjsonで書き出す。
+//! 各ノードは"node"にノードの種類を、"span"に
+//! [開始行, 開始列, 終了行, 終了列]を持つオブジェクトになる
+
+use std::fmt::Write;
+
+use super::ast::*;
+use super::span::Span;
+
+
+/// jsonの値
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(usize),
+    String(String),
+    Array(Vec<Json>),
+    /// キーの順番を保つためにVecで持つ
+    Object(Vec<(&'static str, Json)>),
+}
+
+// 文字列をjsonの文字列にする
+fn quote(s: &str) -> String {
+    let mut q = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => q += "\\\"",
+            '\\' => q += "\\\\",
+            '\n' => q += "\\n",
+            '\r' => q += "\\r",
+            '\t' => q += "\\t",
+            c if (c as u32) < 0x20 => {
+                let _ = write!(q, "\\u{:04x}", c as u32);
+            },
+            c => q.push(c),
+        }
+    }
+    q.push('"');
+    q
+}
+
+impl Json {
+    // 配列やオブジェクトを含まない値か
+    fn is_scalar(&self) -> bool {
+        !matches!(self, Json::Array(_) | Json::Object(_))
+    }
+
+    /// 2つの空白で字下げしたjsonの文字列を返す。
+    /// 数や文字列だけの配列は1行に書く
+    pub fn to_pretty(&self) -> String {
+        let mut s = String::new();
+        self.write_pretty(&mut s, 0);
+        s.push('\n');
+        s
+    }
+
+    fn write_pretty(&self, s: &mut String, depth: usize) {
+        let indent = "  ".repeat(depth + 1);
+        match self {
+            Json::Null => *s += "null",
+            Json::Bool(b) => *s += if *b { "true" } else { "false" },
+            Json::Number(n) => *s += &n.to_string(),
+            Json::String(v) => *s += &quote(v),
+            Json::Array(items) if items.is_empty() => *s += "[]",
+            Json::Array(items) if items.iter().all(Json::is_scalar) => {
+                s.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if 0 < i {
+                        *s += ", ";
+                    }
+                    item.write_pretty(s, depth);
+                }
+                s.push(']');
+            },
+            Json::Array(items) => {
+                *s += "[\n";
+                for (i, item) in items.iter().enumerate() {
+                    if 0 < i {
+                        *s += ",\n";
+                    }
+                    *s += &indent;
+                    item.write_pretty(s, depth + 1);
+                }
+                *s += &format!("\n{}]", "  ".repeat(depth));
+            },
+            Json::Object(members) if members.is_empty() => *s += "{}",
+            Json::Object(members) => {
+                *s += "{\n";
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if 0 < i {
+                        *s += ",\n";
+                    }
+                    *s += &format!("{}{}: ", indent, quote(key));
+                    value.write_pretty(s, depth + 1);
+                }
+                *s += &format!("\n{}}}", "  ".repeat(depth));
+            },
+        }
+    }
+}
+
+
+// ノードのオブジェクトを作る。nodeとspanは全てのノードに付ける
+fn node(name: &str, span: &Span, mut members: Vec<(&'static str, Json)>) -> Json {
+    let mut object = vec![("node", Json::String(name.to_string()))];
+    object.append(&mut members);
+    object.push(("span", Json::Array(vec![
+        Json::Number(span.start_line),
+        Json::Number(span.start_col),
+        Json::Number(span.end_line),
+        Json::Number(span.end_col),
+    ])));
+    Json::Object(object)
+}
+
+fn string(s: &str) -> Json {
+    Json::String(s.to_string())
+}
+
+fn identifier(identifier: &Identifier) -> Json {
+    node("identifier", &identifier.span, vec![("name", string(&identifier.name))])
+}
+
+fn identifiers(names: &[Identifier]) -> Json {
+    Json::Array(names.iter().map(identifier).collect())
+}
+
+fn option<T>(value: &Option<T>, f: impl Fn(&T) -> Json) -> Json {
+    match value {
+        Some(v) => f(v),
+        None => Json::Null
+    }
+}
+
+/// クラスをjsonにする
+pub fn class(class: &Class) -> Json {
+    node("class", &class.span, vec![
+        ("name", identifier(&class.name)),
+        ("classVarDecs", Json::Array(class.class_var_decs.iter().map(class_var_dec).collect())),
+        ("subroutineDecs", Json::Array(class.subroutine_decs.iter().map(subroutine_dec).collect())),
+    ])
+}
+
+fn class_var_dec(dec: &ClassVarDec) -> Json {
+    let kind = match dec.kind {
+        ClassVarKind::Static => "static",
+        ClassVarKind::Field => "field",
+    };
+    node("classVarDec", &dec.span, vec![
+        ("kind", string(kind)),
+        ("type", string(dec.ty.name())),
+        ("names", identifiers(&dec.names)),
+    ])
+}
+
+fn subroutine_dec(dec: &SubroutineDec) -> Json {
+    let parameters = dec.parameters.iter().map(|p| node("parameter", &p.span, vec![
+        ("type", string(p.ty.name())),
+        ("name", identifier(&p.name)),
+    ])).collect();
+    let var_decs = dec.var_decs.iter().map(|v| node("varDec", &v.span, vec![
+        ("type", string(v.ty.name())),
+        ("names", identifiers(&v.names)),
+    ])).collect();
+
+    node("subroutineDec", &dec.span, vec![
+        ("kind", string(dec.kind.keyword())),
+        ("returnType", string(dec.return_type.as_ref().map_or("void", Type::name))),
+        ("name", identifier(&dec.name)),
+        ("parameters", Json::Array(parameters)),
+        ("varDecs", Json::Array(var_decs)),
+        ("statements", statements(&dec.statements)),
+    ])
+}
+
+fn statements(statements: &[Statement]) -> Json {
+    Json::Array(statements.iter().map(statement).collect())
+}
+
+fn statement(statement: &Statement) -> Json {
+    match statement {
+        Statement::Let { name, index, value, span } => node("let", span, vec![
+            ("name", identifier(name)),
+            ("index", option(index, |i| expression(i))),
+            ("value", expression(value)),
+        ]),
+        Statement::If { condition, then_statements, else_statements, span } => {
+            node("if", span, vec![
+                ("condition", expression(condition)),
+                ("then", statements(then_statements)),
+                ("else", option(else_statements, |s| statements(s))),
+            ])
+        },
+        Statement::While { condition, statements: body, span } => node("while", span, vec![
+            ("condition", expression(condition)),
+            ("statements", statements(body)),
+        ]),
+        Statement::Do { call, span } => node("do", span, vec![
+            ("call", subroutine_call(call)),
+        ]),
+        Statement::Return { value, span } => node("return", span, vec![
+            ("value", option(value, expression)),
+        ]),
+    }
+}
+
+fn expression(expression: &Expression) -> Json {
+    let rest = expression.rest.iter().map(|(op, t)| Json::Object(vec![
+        ("op", string(&op.symbol().to_string())),
+        ("term", term(t)),
+    ])).collect();
+    node("expression", &expression.span, vec![
+        ("term", term(&expression.term)),
+        ("rest", Json::Array(rest)),
+    ])
+}
+
+fn term(t: &Term) -> Json {
+    match t {
+        Term::IntegerConstant { value, span } => node("integerConstant", span, vec![
+            ("value", Json::Number(*value)),
+        ]),
+        Term::StringConstant { value, span } => node("stringConstant", span, vec![
+            ("value", string(value)),
+        ]),
+        Term::KeywordConstant { value, span } => {
+            let value = match value {
+                KeywordConstant::True => "true",
+                KeywordConstant::False => "false",
+                KeywordConstant::Null => "null",
+                KeywordConstant::This => "this",
+            };
+            node("keywordConstant", span, vec![("value", string(value))])
+        },
+        Term::VarName(name) => node("varName", &name.span, vec![
+            ("name", string(&name.name)),
+        ]),
+        Term::ArrayAccess { name, index, span } => node("arrayAccess", span, vec![
+            ("name", identifier(name)),
+            ("index", expression(index)),
+        ]),
+        Term::SubroutineCall(call) => subroutine_call(call),
+        Term::Parenthesized { expression: e, span } => node("parenthesized", span, vec![
+            ("expression", expression(e)),
+        ]),
+        Term::Unary { op, term: operand, span } => node("unary", span, vec![
+            ("op", string(&op.symbol().to_string())),
+            ("term", term(operand)),
+        ]),
+    }
+}
+
+fn subroutine_call(call: &SubroutineCall) -> Json {
+    node("subroutineCall", &call.span, vec![
+        ("receiver", option(&call.receiver, identifier)),
+        ("name", identifier(&call.name)),
+        ("arguments", Json::Array(call.arguments.iter().map(expression).collect())),
+    ])
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::Json;
+    use super::class;
+    use crate::ast::*;
+    use crate::span::Span;
+
+    #[test]
+    fn test_json_to_pretty() {
+        let json = Json::Object(vec![
+            ("a", Json::Array(vec![Json::Number(1), Json::Null, Json::Bool(true)])),
+            ("b", Json::String("\"x\"\n\\".to_string())),
+            ("c", Json::Array(vec![Json::Object(Vec::new())])),
+            ("d", Json::Array(Vec::new())),
+        ]);
+        assert_eq!(json.to_pretty(), "{\n  \"a\": [1, null, true],\n  \
+                                      \"b\": \"\\\"x\\\"\\n\\\\\",\n  \
+                                      \"c\": [\n    {}\n  ],\n  \"d\": []\n}\n");
+    }
+
+    #[test]
+    fn test_json_class() {
+        let span = Span { start_line: 1, start_col: 1, end_line: 1, end_col: 11, ..Span::default() };
+        let c = Class {
+            name: Identifier { name: "A".to_string(), span: span.clone() },
+            class_var_decs: Vec::new(),
+            subroutine_decs: Vec::new(),
+            span,
+        };
+        assert_eq!(class(&c).to_pretty(), "\
+{
+  \"node\": \"class\",
+  \"name\": {
+    \"node\": \"identifier\",
+    \"name\": \"A\",
+    \"span\": [1, 1, 1, 11]
+  },
+  \"classVarDecs\": [],
+  \"subroutineDecs\": [],
+  \"span\": [1, 1, 1, 11]
+}
+");
+    }
+}
@@ -0,0 +1,465 @@
+//! Jackのコンパイラ。
+//! compile_strでソースコードを1回の呼び出しでコンパイルできる。
+//! トークナイザや構文解析器、出力の各部分も個別に使える
+
+use std::cell::RefCell;
+use std::io;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+pub mod tokenizer;
+pub mod compilation_engine;
+pub mod symbol_table;
+pub mod vm_writer;
+pub mod span;
+pub mod ast;
+pub mod xml_writer;
+pub mod code_generator;
+pub mod error;
+pub mod diagnostic;
+pub mod output;
+pub mod json_writer;
+pub mod vm_translator;
+pub mod message;
+pub mod resolver;
+pub mod type_checker;
+pub mod signature;
+
+pub use tokenizer::Tokenizer;
+pub use tokenizer::token::Token;
+pub use symbol_table::{SymbolTable, SymbolInfo};
+pub use compilation_engine::{CompilationEngine, Target, DEFAULT_MAX_ERRORS};
+pub use xml_writer::XmlWriter;
+pub use code_generator::CodeGenerator;
+pub use error::CompileError;
+pub use diagnostic::{Diagnostic, Severity};
+pub use message::Lang;
+pub use signature::Registry;
+
+use ast::Class;
+
+
+/// 出力の形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Emit {
+    /// トークンを並べたxml(T.xml)
+    Tokens,
+    /// 構文木のxml
+    Xml,
+    /// vmコード
+    Vm,
+    /// Hackのアセンブリ。1つのクラスだけを変換したもので、ブートストラップの
+    /// コードはない。実行できるプログラムはvm_translator::translate_programで作る
+    Asm,
+    /// 構文木のjson
+    AstJson,
+}
+
+impl Emit {
+    /// tokens、xml、vm、asm、ast-jsonのいずれかの名前から形式を返す
+    pub fn from_name(name: &str) -> Option<Emit> {
+        match name {
+            "tokens" => Some(Emit::Tokens),
+            "xml" => Some(Emit::Xml),
+            "vm" => Some(Emit::Vm),
+            "asm" => Some(Emit::Asm),
+            "ast-json" => Some(Emit::AstJson),
+            _ => None
+        }
+    }
+
+    /// 出力するファイルの拡張子から形式を返す。output_pathと同じく、
+    /// MainT.xmlのようにT.xmlで終わるときはトークンのXMLにする
+    pub fn from_path(path: &Path) -> Option<Emit> {
+        match path.extension()?.to_str()? {
+            "xml" if path.file_stem()?.to_str()?.ends_with('T') => Some(Emit::Tokens),
+            "xml" => Some(Emit::Xml),
+            "vm" => Some(Emit::Vm),
+            "asm" => Some(Emit::Asm),
+            "json" => Some(Emit::AstJson),
+            _ => None
+        }
+    }
+
+    /// 入力のファイル名から出力のファイル名を作る。Main.jackならMainT.xml、
+    /// Main.xml、Main.vm、Main.asm、Main.ast.jsonになる
+    pub fn output_path(self, input: &Path) -> PathBuf {
+        match self {
+            Emit::Tokens => {
+                let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+                input.with_file_name(format!("{}T.xml", stem))
+            },
+            Emit::Xml => input.with_extension("xml"),
+            Emit::Vm => input.with_extension("vm"),
+            Emit::Asm => input.with_extension("asm"),
+            Emit::AstJson => input.with_extension("ast.json"),
+        }
+    }
+}
+
+/// コンパイルの設定
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    pub emit: Emit,
+    /// 1つのファイルで報告するエラーの最大数
+    pub max_errors: usize,
+    /// トークンの始まりにならない文字をエラーにせず、警告にして読み飛ばす
+    pub lenient: bool,
+    /// 警告をエラーとして扱う
+    pub warnings_as_errors: bool,
+    /// サブルーチンの変数や引数がクラスの変数を隠すときに警告する
+    pub warn_shadowing: bool,
+    /// 型の誤りをエラーにする
+    pub strict_types: bool,
+    /// 診断を表示する言語
+    pub lang: Lang,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            emit: Emit::Vm,
+            max_errors: DEFAULT_MAX_ERRORS,
+            lenient: false,
+            warnings_as_errors: false,
+            warn_shadowing: false,
+            strict_types: false,
+            lang: Lang::En,
+        }
+    }
+}
+
+/// コンパイルの結果
+#[derive(Debug, Clone, PartialEq)]
+pub struct Output {
+    pub emit: Emit,
+    /// 出力の中身
+    pub text: String,
+    /// コンパイルは止めなかった警告
+    pub warnings: Vec<Diagnostic>,
+}
+
+// 読んだバイトを残しておくリーダー。読みながらコンパイルした後でも、
+// 診断にソースコードの行を表示できるようにする
+struct Recorder<R> {
+    inner: R,
+    read: Rc<RefCell<Vec<u8>>>,
+}
+
+impl<R: Read> Read for Recorder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read.borrow_mut().extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+// 設定に合わせたトークナイザを作る
+fn tokenizer<R: Read>(source: R, file: Option<&str>, options: &Options) -> Tokenizer<R> {
+    let mut t = match file {
+        Some(file) => Tokenizer::with_file(source, file),
+        None => Tokenizer::new(source),
+    };
+    t.set_lenient(options.lenient);
+    t
+}
+
+// sourceの構文木を返す。構文解析できなければNoneを返す
+fn parse(source: &[u8], file: Option<&str>, options: &Options) -> Option<Class> {
+    let mut c = CompilationEngine::new(tokenizer(source, file, options), io::sink(), Target::Xml);
+    c.tokenizer.advance();
+    c.parse_class().ok()
+}
+
+/// sourceをコンパイルした結果を返す。fileはエラーの位置に使うファイル名。
+/// Jack OS以外の他のクラスのサブルーチンの呼び出しは確かめない
+pub fn compile(source: &[u8], file: Option<&str>, options: &Options)
+    -> Result<Output, Vec<Diagnostic>> {
+    compile_with(source, file, options, None)
+}
+
+/// readerから読みながらコンパイルした結果を返す。標準入力のようなシークできない
+/// 入力も、先に全てを読み込まずにコンパイルできる
+pub fn compile_reader<R: Read>(reader: R, file: Option<&str>, options: &Options)
+    -> Result<Output, Vec<Diagnostic>> {
+    compile_with(reader, file, options, None)
+}
+
+/// 複数のファイルをまとめてコンパイルし、sourcesと同じ順に結果を返す。
+/// 先に全てのファイルからサブルーチンのシグネチャを集めてから、それぞれの
+/// ファイルをコンパイルするので、他のクラスの呼び出しも確かめられる
+pub fn compile_project(sources: &[(&[u8], Option<&str>)], options: &Options)
+    -> Vec<Result<Output, Vec<Diagnostic>>> {
+    let mut registry = Registry::with_os();
+    if options.emit != Emit::Tokens {
+        for (source, file) in sources {
+            if let Some(class) = parse(source, *file, options) {
+                registry.add_class(&class);
+            }
+        }
+    }
+
+    let registry = Rc::new(registry);
+    sources.iter()
+        .map(|(source, file)| compile_with(*source, *file, options, Some(registry.clone())))
+        .collect()
+}
+
+fn compile_with<R: Read>(source: R, file: Option<&str>, options: &Options,
+                         registry: Option<Rc<Registry>>) -> Result<Output, Vec<Diagnostic>> {
+    let read = Rc::new(RefCell::new(Vec::new()));
+    let mut t = tokenizer(Recorder { inner: source, read: read.clone() }, file, options);
+
+    let mut output = Vec::new();
+    let (result, mut warnings) = match options.emit {
+        // トークンだけを出力するときは構文解析をしない
+        Emit::Tokens => {
+            let result = xml_writer::write_tokens(&mut t, &mut output);
+            (result, t.take_warnings())
+        },
+        Emit::Xml | Emit::Vm | Emit::Asm | Emit::AstJson => {
+            let target = match options.emit {
+                Emit::Xml => Target::Xml,
+                Emit::AstJson => Target::Json,
+                _ => Target::Vm,
+            };
+            let mut c = CompilationEngine::new(t, &mut output, target);
+            c.set_max_errors(options.max_errors);
+            c.set_warn_shadowing(options.warn_shadowing);
+            c.set_strict_types(options.strict_types);
+            if let Some(registry) = registry {
+                c.set_registry(registry);
+            }
+            c.tokenizer.advance();
+            let result = c.compile_class();
+            (result, c.take_warnings())
+        }
+    };
+
+    let failed = result.is_err();
+    let mut errors = result.err().unwrap_or_default();
+    if options.warnings_as_errors {
+        errors.append(&mut warnings);
+    }
+
+    // UTF-8として正しくない部分があっても該当行を表示できるようにする
+    let source = read.borrow();
+    let source = String::from_utf8_lossy(&source);
+    let lang = options.lang;
+    let warnings = warnings.into_iter()
+        .map(|w| Diagnostic::with_lang(Severity::Warning, w, &source, lang));
+    if failed || !errors.is_empty() {
+        let mut diagnostics: Vec<Diagnostic> = errors.into_iter()
+            .map(|e| Diagnostic::with_lang(Severity::Error, e, &source, lang))
+            .chain(warnings)
+            .collect();
+        // ソースコードの順に並べる
+        diagnostics.sort_by_key(|d| d.error.span().map(|s| s.byte_range.start));
+        return Err(diagnostics)
+    }
+
+    let mut text = String::from_utf8_lossy(&output).into_owned();
+    if options.emit == Emit::Asm {
+        text = match vm_translator::translate(&text) {
+            Ok(asm) => asm,
+            Err(e) => return Err(vec![Diagnostic::with_lang(Severity::Error, e.into(), "", lang)])
+        };
+    }
+    Ok(Output { emit: options.emit, text, warnings: warnings.collect() })
+}
+
+/// 文字列のソースコードをコンパイルした結果を返す
+pub fn compile_str(source: &str, options: &Options) -> Result<Output, Vec<Diagnostic>> {
+    compile(source.as_bytes(), None, options)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // test/vmのプログラムとvmファイルの組。vmファイルは構文木を作るように
+    // 書き換える前のコンパイラが出力したもの
+    macro_rules! program {
+        ($($name:literal),*) => {
+            [$((concat!($name, ".jack"),
+                include_str!(concat!("../test/vm/", $name, ".jack")),
+                include_str!(concat!("../test/vm/", $name, ".vm")))),*]
+        };
+    }
+
+    #[test]
+    fn test_compile_programs() {
+        let programs: [&[(&str, &str, &str)]; 5] = [
+            &program!("Seven/Main"),
+            &program!("ConvertToBin/Main"),
+            &program!("Average/Main"),
+            &program!("ComplexArrays/Main"),
+            &program!("Square/Main", "Square/Square", "Square/SquareGame"),
+        ];
+        for program in programs {
+            let sources: Vec<(&[u8], Option<&str>)> = program.iter()
+                .map(|(name, jack, _)| (jack.as_bytes(), Some(*name)))
+                .collect();
+            let results = compile_project(&sources, &Options::default());
+            for ((name, _, vm), result) in program.iter().zip(results) {
+                assert_eq!(result.map(|o| o.text).as_deref(), Ok(*vm), "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_compile_str() {
+        let source = "class A { function void f() { return; } }";
+        let output = compile_str(source, &Options::default()).unwrap();
+        assert_eq!(output.text, "function A.f 0\npush constant 0\nreturn\n");
+
+        let options = Options { emit: Emit::Tokens, ..Options::default() };
+        let output = compile_str("let x;", &options).unwrap();
+        assert_eq!(output.text, "<tokens>\n<keyword> let </keyword>\n\
+                                 <identifier> x </identifier>\n\
+                                 <symbol> ; </symbol>\n</tokens>\n");
+
+        let errors = compile_str("class A {\n  field int x y;\n}", &Options::default())
+            .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].error.span().unwrap().to_string(), "2:15");
+        assert!(errors[0].rendered.starts_with("error[E0001]: unexpected token: 'y'\n --> 2:15\n"));
+    }
+
+    // 途中から読み出せなくなるリーダー
+    struct Broken<'a>(&'a [u8]);
+
+    impl Read for Broken<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Err(io::Error::other("broken pipe"))
+            }
+            self.0.read(buf)
+        }
+    }
+
+    #[test]
+    fn test_compile_reader() {
+        let source = "class A {\n  field int x y;\n}";
+        let errors = compile_reader(source.as_bytes(), Some("A.jack"), &Options::default())
+            .unwrap_err();
+        // 読みながらコンパイルしても該当行を表示する
+        assert!(errors[0].rendered.contains("  field int x y;\n"), "{}", errors[0].rendered);
+
+        let errors = compile_reader(Broken(b"class A {"), Some("<stdin>"), &Options::default())
+            .unwrap_err();
+        assert!(errors.iter().any(|d| d.error == CompileError::Io {
+            path: "<stdin>".to_string(),
+            kind: io::ErrorKind::Other,
+            message: "broken pipe".to_string(),
+        }), "{:?}", errors);
+    }
+
+    #[test]
+    fn test_compile_warnings() {
+        let source = "class A { @ function void f() { return; } }";
+        let options = Options { lenient: true, ..Options::default() };
+        let output = compile_str(source, &options).unwrap();
+        assert_eq!(output.warnings.len(), 1);
+        assert!(output.warnings[0].rendered.starts_with("warning[E0006]: unknown start of token: @"));
+
+        // 警告をエラーとして扱う
+        let options = Options { warnings_as_errors: true, ..options };
+        let errors = compile_str(source, &options).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].rendered.starts_with("error[E0006]: unknown start of token: @"));
+
+        let options = Options { lang: Lang::Ja, ..options };
+        let errors = compile_str(source, &options).unwrap_err();
+        assert!(errors[0].rendered.starts_with("エラー[E0006]: トークンの始まりにならない文字です: @"));
+    }
+
+    #[test]
+    fn test_compile_declarations() {
+        let source = "class A {\n  field int x;\n  method void f(int x) {\n    \
+                      var int y, y;\n    return;\n  }\n}";
+        let errors = compile_str(source, &Options::default()).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].rendered
+            .starts_with("error[E0012]: 'y' is already declared at 4:13\n --> 4:16\n"));
+
+        let source = source.replace("y, y", "y");
+        assert!(compile_str(&source, &Options::default()).unwrap().warnings.is_empty());
+        let options = Options { warn_shadowing: true, ..Options::default() };
+        let output = compile_str(&source, &options).unwrap();
+        assert_eq!(output.warnings.len(), 1);
+        assert!(output.warnings[0].rendered
+            .starts_with("warning[E0013]: 'x' shadows the class variable declared at 2:13\n --> 3:21\n"));
+    }
+
+    #[test]
+    fn test_compile_max_errors_zero() {
+        // 上限が0でもエラーを報告し、成功とはしない
+        let source = "class A { function void f() { let y = 1; return; } }";
+        let options = Options { max_errors: 0, ..Options::default() };
+        let errors = compile_str(source, &options).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].rendered.starts_with("error[E0008]: undefined variable: 'y'"));
+    }
+
+    #[test]
+    fn test_compile_strict_types() {
+        let source = "class A { function void f() { var int i; let i = \"x\"; return; } }";
+        assert!(compile_str(source, &Options::default()).is_ok());
+
+        let options = Options { strict_types: true, ..Options::default() };
+        let errors = compile_str(source, &options).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].rendered
+            .starts_with("error[E0014]: mismatched types: expected int, found String\n --> 1:50\n"));
+    }
+
+    #[test]
+    fn test_compile_project() {
+        let main = "class Main {\n  function void main() {\n    \
+                    do P.new(1);\n    do P.draw();\n    return;\n  }\n}";
+        let p = "class P {\n  constructor P new(int x, int y) { return this; }\n  \
+                 method void draw() { return; }\n}";
+
+        // 1つのファイルだけでは他のクラスの呼び出しを確かめない
+        assert!(compile_str(main, &Options::default()).is_ok());
+
+        let sources = [(main.as_bytes(), Some("Main.jack")), (p.as_bytes(), Some("P.jack"))];
+        let results = compile_project(&sources, &Options::default());
+        assert_eq!(results.len(), 2);
+        let errors = results[0].as_ref().unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].rendered
+            .starts_with("error[E0018]: 'P.new' takes 2 arguments but 1 were given\n --> Main.jack:3:8\n"));
+        assert!(errors[1].rendered.starts_with("error[E0019]: 'P.draw' is a method"));
+        assert!(results[1].is_ok());
+    }
+
+    #[test]
+    fn test_compile_asm_and_json() {
+        let source = "class A { function int f() { return 1; } }";
+        let options = Options { emit: Emit::Asm, ..Options::default() };
+        let output = compile_str(source, &options).unwrap();
+        assert!(output.text.starts_with("(A.f)\n@1\nD=A\n"));
+
+        let options = Options { emit: Emit::AstJson, ..Options::default() };
+        let output = compile_str(source, &options).unwrap();
+        assert!(output.text.starts_with("{\n  \"node\": \"class\",\n"));
+        assert!(output.text.contains("\"node\": \"integerConstant\",\n"));
+    }
+
+    #[test]
+    fn test_emit_output_path() {
+        let input = Path::new("dir/Main.jack");
+        assert_eq!(Emit::Tokens.output_path(input), Path::new("dir/MainT.xml"));
+        assert_eq!(Emit::Xml.output_path(input), Path::new("dir/Main.xml"));
+        assert_eq!(Emit::Vm.output_path(input), Path::new("dir/Main.vm"));
+        assert_eq!(Emit::AstJson.output_path(input), Path::new("dir/Main.ast.json"));
+        assert_eq!(Emit::from_path(Path::new("out/Main.asm")), Some(Emit::Asm));
+        assert_eq!(Emit::from_path(Path::new("Main")), None);
+        assert_eq!(Emit::from_path(Path::new("out/MainT.xml")), Some(Emit::Tokens));
+        assert_eq!(Emit::from_path(Path::new("out/Main.xml")), Some(Emit::Xml));
+    }
+}
@@ -1,38 +1,669 @@
+use std::fs;
 use std::fs::File;
-use std::io::BufReader;
+use std::io;
+use std::io::{Read, Write};
 use std::env;
+use std::path::{Path, PathBuf};
+use std::process;
 
-mod tokenizer;
-use tokenizer::Tokenizer;
-mod compilation_engine;
-use compilation_engine::CompilationEngine;
-mod symbol_table;
+use compiler::{Emit, Options, CompileError, Diagnostic, Severity, Lang, DEFAULT_MAX_ERRORS};
+use compiler::output;
+use compiler::vm_translator;
+use compiler::message::{Code, Message};
 
 
-fn main() {
-    let f = match env::args().nth(1) {
-        Some(f) => match File::open(f) {
-            Ok(f) => f,
-            Err(_) => return println!("ファイルが開けません")
+// 終了コード。成功したときは0を返す
+const EXIT_COMPILE_ERROR: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_IO_ERROR: i32 = 3;
+
+// 失敗の理由。複数のファイルで失敗したときは入出力のエラーを優先する
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Failure {
+    Compile,
+    Io,
+}
+
+impl Failure {
+    fn exit_code(self) -> i32 {
+        match self {
+            Failure::Compile => EXIT_COMPILE_ERROR,
+            Failure::Io => EXIT_IO_ERROR,
+        }
+    }
+}
+
+// コンパイルした入力の数と失敗した数。worstは失敗の中で最も優先する理由
+#[derive(Debug, PartialEq, Eq)]
+struct Summary {
+    total: usize,
+    failures: usize,
+    worst: Option<Failure>,
+}
+
+// 引数の誤りを標準エラー出力に表示して終了する
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(EXIT_USAGE)
+}
+
+// 入力のファイル名。標準入力のときは<stdin>にする
+fn input_name(input: &Path) -> String {
+    if input == Path::new("-") {
+        "<stdin>".to_string()
+    } else {
+        input.display().to_string()
+    }
+}
+
+// 入力のソースコードを読む。標準入力は読み込まずにトークナイザへ直接渡すので、
+// "-"のときはNoneを返す
+fn read_source(input: &Path) -> Result<Option<Vec<u8>>, CompileError> {
+    if input == Path::new("-") {
+        return Ok(None);
+    }
+    let mut source = Vec::new();
+    match File::open(input).and_then(|mut f| f.read_to_end(&mut source)) {
+        Ok(_) => Ok(Some(source)),
+        Err(e) => Err(CompileError::io(&input_name(input), &e))
+    }
+}
+
+// コマンドラインで指定した設定
+#[derive(Debug, Default, PartialEq)]
+struct Args {
+    inputs: Vec<PathBuf>,
+    emit: Option<Emit>,
+    // -oで指定した出力先
+    output: Option<PathBuf>,
+    out_dir: Option<PathBuf>,
+    max_errors: Option<usize>,
+    lenient: bool,
+    warnings_as_errors: bool,
+    warn_shadowing: bool,
+    strict_types: bool,
+    quiet: bool,
+}
+
+// 実行すること
+#[derive(Debug, PartialEq)]
+enum Command {
+    Help,
+    Version,
+    Compile(Args),
+}
+
+// オプションの値を返す。"--name=値"の形なら=の後を、そうでなければ
+// 次の引数を値とする
+fn option_value(name: &str, inline: Option<String>, args: &mut impl Iterator<Item = String>)
+    -> Result<String, Message> {
+    inline.or_else(|| args.next())
+        .ok_or_else(|| Message::new(Code::MissingValue, &[name]))
+}
+
+// 表示する言語を決める。引数の誤りも選んだ言語で表示したいので、他の引数を
+// 読む前に--langだけを探す。--langがなければ環境変数LANGから決める
+fn select_lang(args: &[String], locale: Option<&str>) -> Lang {
+    let mut lang = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = match arg.strip_prefix("--lang") {
+            Some("") => args.next().map(String::as_str),
+            Some(rest) => rest.strip_prefix('='),
+            None => None,
+        };
+        // 誤った値はparse_argsで報告する
+        if let Some(l) = value.and_then(Lang::from_name) {
+            lang = Some(l);
+        }
+    }
+    lang.unwrap_or_else(|| locale.map_or(Lang::En, Lang::from_locale))
+}
+
+// コマンドラインの引数を読む。誤りがあればその説明を返す
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Command, Message> {
+    let mut args = args.into_iter();
+    let mut a = Args::default();
+
+    while let Some(arg) = args.next() {
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) if arg.starts_with("--") => {
+                (name.to_string(), Some(value.to_string()))
+            },
+            _ => (arg.clone(), None)
+        };
+
+        match name.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "--emit" => {
+                let value = option_value(&name, inline, &mut args)?;
+                match Emit::from_name(&value) {
+                    Some(emit) => a.emit = Some(emit),
+                    None => return Err(Message::new(Code::InvalidEmit, &[]))
+                }
+            },
+            "-o" => a.output = Some(PathBuf::from(option_value(&name, inline, &mut args)?)),
+            "--out-dir" => {
+                a.out_dir = Some(PathBuf::from(option_value(&name, inline, &mut args)?));
+            },
+            // 0では報告するエラーがなくなるので1以上にする
+            "--max-errors" => match option_value(&name, inline, &mut args)?.parse() {
+                Ok(n) if 0 < n => a.max_errors = Some(n),
+                _ => return Err(Message::new(Code::InvalidMaxErrors, &[]))
+            },
+            // 言語はselect_langで決めるので、ここでは値を確かめるだけ
+            "--lang" => if Lang::from_name(&option_value(&name, inline, &mut args)?).is_none() {
+                return Err(Message::new(Code::InvalidLang, &[]))
+            },
+            "--lenient" => a.lenient = true,
+            "--warnings-as-errors" => a.warnings_as_errors = true,
+            "--warn-shadowing" => a.warn_shadowing = true,
+            "--strict-types" => a.strict_types = true,
+            "-q" | "--quiet" => a.quiet = true,
+            // -だけのときは標準入力
+            "-" => a.inputs.push(PathBuf::from(arg)),
+            _ if name.starts_with('-') => return Err(Message::new(Code::UnknownOption, &[&arg])),
+            _ => a.inputs.push(PathBuf::from(arg)),
+        }
+    }
+
+    if a.inputs.is_empty() {
+        return Err(Message::new(Code::NoInput, &[]))
+    }
+    if a.output.is_some() && a.out_dir.is_some() {
+        return Err(Message::new(Code::OutputConflict, &[]))
+    }
+    Ok(Command::Compile(a))
+}
+
+// コンパイルした結果の警告とエラーを標準エラー出力に表示し、成功していれば
+// 出力する文字列を返す
+fn take_output(result: Result<compiler::Output, Vec<Diagnostic>>, quiet: bool)
+    -> Result<String, Failure> {
+    match result {
+        Ok(o) => {
+            if !quiet {
+                for w in &o.warnings {
+                    eprint!("{}", w);
+                }
+            }
+            Ok(o.text)
+        },
+        Err(diagnostics) => {
+            for d in diagnostics.iter().filter(|d| !quiet || d.severity == Severity::Error) {
+                eprint!("{}", d);
+            }
+            // 読み出しに失敗したときは入出力のエラーにする
+            if diagnostics.iter().any(|d| matches!(d.error, CompileError::Io { .. })) {
+                Err(Failure::Io)
+            } else {
+                Err(Failure::Compile)
+            }
         }
-        None => return println!("ファイル名を指定してください")
+    }
+}
+
+// output_textをoutputに書き込む。outputが"-"のときは標準出力に書き出す
+fn write_output(output_text: &str, output: &Path, lang: Lang) -> Result<(), Failure> {
+    let report = |e| report(e, lang);
+    if output == Path::new("-") {
+        return io::stdout().write_all(output_text.as_bytes())
+            .map_err(|e| report(CompileError::io("<stdout>", &e)))
+    }
+    // 一時ファイルに書いてから名前を変えるので、失敗しても途中まで書いた
+    // ファイルは残らない
+    output::write_file(output, output_text.as_bytes()).map_err(report)
+}
+
+// 入出力のエラーを標準エラー出力に表示する
+fn report(error: CompileError, lang: Lang) -> Failure {
+    eprint!("{}", Diagnostic::with_lang(Severity::Error, error, "", lang));
+    Failure::Io
+}
+
+// ディレクトリにある、拡張子がextensionのファイルのパスを名前順で返す
+fn files_with_extension(dir: &Path, extension: &str) -> Result<Vec<PathBuf>, CompileError> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => return Err(CompileError::io(&dir.display().to_string(), &e))
     };
 
-    let o = match env::args().nth(2) {
-        Some(f) => match File::create(f) {
-            Ok(f) => f,
-            Err(_) => return println!("ファイルが開けません")
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_file() && p.extension().is_some_and(|e| e == extension))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+// ディレクトリにあるjackファイルのパスを名前順で返す
+fn jack_files(dir: &Path) -> Result<Vec<PathBuf>, CompileError> {
+    files_with_extension(dir, "jack")
+}
+
+// ディレクトリにあるOSなどのvmファイルのパスを名前順で返す。
+// 同じ名前のjackファイルがあるものはコンパイルした結果なので除く
+fn library_files(dir: &Path) -> Result<Vec<PathBuf>, CompileError> {
+    let files = files_with_extension(dir, "vm")?;
+    Ok(files.into_iter().filter(|p| !p.with_extension("jack").exists()).collect())
+}
+
+// リンクするvmファイルを読む
+fn read_library(path: &Path) -> Result<String, CompileError> {
+    fs::read_to_string(path).map_err(|e| CompileError::io(&path.display().to_string(), &e))
+}
+
+// 入力に対応する出力のパスを返す。出力先を指定しなかったときは入力と同じ
+// ディレクトリに、形式に合わせた名前で書き込む。標準入力のときは標準出力にする
+fn output_path(input: &Path, emit: Emit, file: Option<&Path>, dir: Option<&Path>)
+    -> Result<PathBuf, Message> {
+    let stdin = input == Path::new("-");
+    match (file, dir) {
+        (Some(file), _) => Ok(file.to_path_buf()),
+        (None, Some(_)) if stdin => Err(Message::new(Code::StdinOutDir, &[])),
+        (None, Some(dir)) => {
+            Ok(dir.join(emit.output_path(input).file_name().unwrap_or_default()))
+        },
+        (None, None) if stdin => Ok(PathBuf::from("-")),
+        (None, None) => Ok(emit.output_path(input)),
+    }
+}
+
+// 出力するファイルと出力先のディレクトリを返す。-oは入力のファイルが1つで、
+// ディレクトリでないときだけ出力するファイルとして扱い、それ以外は出力先の
+// ディレクトリとする。-o -のときは入力がいくつあっても全て標準出力に書き出す
+fn output_target(output: Option<PathBuf>, out_dir: Option<PathBuf>, single: bool)
+    -> (Option<PathBuf>, Option<PathBuf>) {
+    match (output, out_dir) {
+        (Some(o), _) if o == Path::new("-") => (Some(o), None),
+        (Some(o), _) if single && !o.is_dir() => (Some(o), None),
+        (Some(o), _) => (None, Some(o)),
+        (None, dir) => (None, dir),
+    }
+}
+
+// アセンブリのプログラムを書き込むパスを返す。ディレクトリを1つだけ指定した
+// ときはディレクトリの名前に、それ以外は最初の入力の名前に合わせる
+fn program_path(args_inputs: &[PathBuf], inputs: &[PathBuf], file: Option<&Path>,
+                dir: Option<&Path>) -> Result<PathBuf, Message> {
+    if let Some(file) = file {
+        return Ok(file.to_path_buf())
+    }
+    match args_inputs {
+        [input] if input.is_dir() => {
+            let name = fs::canonicalize(input).ok()
+                .and_then(|p| p.file_name().map(|n| n.to_string_lossy().into_owned()))
+                .unwrap_or_else(|| "Main".to_string());
+            let file_name = format!("{}.asm", name);
+            Ok(dir.unwrap_or(input).join(file_name))
+        },
+        _ => output_path(&inputs[0], Emit::Asm, None, dir),
+    }
+}
+
+// 全ての入力をコンパイルし、その結果をまとめて返す。
+// 入力を読む前に失敗したときはその理由を返す
+fn run(args: Args, lang: Lang) -> Result<Summary, Failure> {
+    let report = |e| report(e, lang);
+    // ディレクトリはその中のjackファイルに展開する
+    let mut inputs = Vec::new();
+    let mut has_dir = false;
+    for input in &args.inputs {
+        if !input.is_dir() {
+            inputs.push(input.clone());
+            continue
         }
-        None => return println!("出力するファイル名を指定してください")
+        let files = jack_files(input).map_err(report)?;
+        if files.is_empty() {
+            eprintln!("{}", Code::NoJackFiles.format(lang, &[&input.display().to_string()]));
+            return Err(Failure::Io)
+        }
+        inputs.extend(files);
+        has_dir = true;
+    }
+
+    // アセンブリは全ての入力を1つのプログラムにする
+    let linked = args.emit.or_else(|| args.output.as_deref().and_then(Emit::from_path))
+        == Some(Emit::Asm);
+    let single = (inputs.len() == 1 && !has_dir) || linked;
+    let (file, dir) = output_target(args.output, args.out_dir, single);
+    if let Some(dir) = &dir {
+        fs::create_dir_all(dir)
+            .map_err(|e| report(CompileError::io(&dir.display().to_string(), &e)))?;
+    }
+
+    // --emitがないときは出力するファイルの拡張子から形式を決める
+    let emit = args.emit
+        .or_else(|| file.as_deref().and_then(Emit::from_path))
+        .unwrap_or(Emit::Vm);
+    let options = Options {
+        // アセンブリはvmコードを集めてから変換する
+        emit: if emit == Emit::Asm { Emit::Vm } else { emit },
+        max_errors: args.max_errors.unwrap_or(DEFAULT_MAX_ERRORS),
+        lenient: args.lenient,
+        warnings_as_errors: args.warnings_as_errors,
+        warn_shadowing: args.warn_shadowing,
+        strict_types: args.strict_types,
+        lang,
+    };
+
+    let mut failures = 0;
+    let mut worst = None;
+    let mut fail = |f| {
+        failures += 1;
+        worst = worst.max(Some(f));
+    };
+
+    // 他のクラスの呼び出しを確かめるため、先に全ての入力を読んでから
+    // まとめてコンパイルする
+    let mut sources = Vec::new();
+    for input in &inputs {
+        let output = match output_path(input, emit, file.as_deref(), dir.as_deref()) {
+            Ok(output) => output,
+            Err(e) => usage_error(&e.text(lang))
+        };
+        match read_source(input) {
+            Ok(source) => sources.push((input_name(input), source, output)),
+            Err(e) => fail(report(e)),
+        }
+    }
+
+    let project: Vec<(&[u8], Option<&str>)> = sources.iter()
+        .filter_map(|(name, source, _)| Some((source.as_deref()?, Some(name.as_str()))))
+        .collect();
+    let mut project_results = compiler::compile_project(&project, &options).into_iter();
+    // 標準入力は読みながらコンパイルし、入力の順に結果を並べる
+    let results: Vec<_> = sources.iter()
+        .map(|(name, source, _)| match source {
+            Some(_) => project_results.next().expect("compile_project returns one result per source"),
+            None => compiler::compile_reader(io::stdin().lock(), Some(name), &options),
+        })
+        .collect();
+    if emit == Emit::Asm {
+        let output = match program_path(&args.inputs, &inputs, file.as_deref(), dir.as_deref()) {
+            Ok(output) => output,
+            Err(e) => usage_error(&e.text(lang))
+        };
+        let mut classes = Vec::new();
+        for result in results {
+            match take_output(result, args.quiet) {
+                Ok(vm) => classes.push(vm),
+                Err(f) => fail(f),
+            }
+        }
+        let compiled = classes.len() == inputs.len();
+        // 入力のディレクトリにあるOSのvmファイルも一緒につなげる
+        for dir in args.inputs.iter().filter(|i| i.is_dir()) {
+            for library in library_files(dir).map_err(report)? {
+                classes.push(read_library(&library).map_err(report)?);
+            }
+        }
+        // 1つでも失敗したらプログラムは書かない
+        if compiled {
+            let classes: Vec<&str> = classes.iter().map(String::as_str).collect();
+            match vm_translator::translate_program(&classes) {
+                Ok(asm) => if let Err(f) = write_output(&asm, &output, lang) {
+                    fail(f);
+                },
+                Err(errors) => {
+                    for e in errors {
+                        eprint!("{}", Diagnostic::with_lang(Severity::Error, e.into(), "", lang));
+                    }
+                    fail(Failure::Compile);
+                },
+            }
+        }
+    } else {
+        for ((_, _, output), result) in sources.iter().zip(results) {
+            if let Err(f) = take_output(result, args.quiet)
+                .and_then(|text| write_output(&text, output, lang)) {
+                fail(f);
+            }
+        }
+    }
+
+    if 1 < inputs.len() && !args.quiet {
+        let counts = [inputs.len(), inputs.len() - failures, failures].map(|n| n.to_string());
+        let summary = Code::Summary.format(lang, &[&counts[0], &counts[1], &counts[2]]);
+        // 診断と同じく標準エラー出力に書き、出力したコードに混ざらないようにする
+        eprintln!("{}", summary);
+    }
+    Ok(Summary { total: inputs.len(), failures, worst })
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let lang = select_lang(&args, env::var("LANG").ok().as_deref());
+
+    let args = match parse_args(args) {
+        Ok(Command::Help) => return print!("{}", Code::Help.template(lang)),
+        Ok(Command::Version) => {
+            return println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+        },
+        Ok(Command::Compile(args)) => args,
+        Err(e) => usage_error(&format!("{}\n{}", e.text(lang), Code::SeeHelp.template(lang)))
     };
 
-    let reader = BufReader::new(f);
-    let t = Tokenizer::new(reader);
-    let mut c = CompilationEngine::new(t, o);
+    match run(args, lang) {
+        Ok(Summary { worst: Some(f), .. }) | Err(f) => process::exit(f.exit_code()),
+        Ok(_) => ()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::io;
+    use std::path::{Path, PathBuf};
+
+    use compiler::{Emit, Lang};
+    use compiler::message::Code;
+    use super::{parse_args, output_path, output_target, program_path, select_lang, Args, Command};
+    use super::{jack_files, library_files, run, take_output, CompileError, Diagnostic};
+    use super::{Failure, Summary, EXIT_USAGE};
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    fn parse(args: &[&str]) -> Result<Command, Code> {
+        parse_args(strings(args)).map_err(|e| e.code)
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(parse(&["--warn-shadowing", "A.jack", "--strict-types"]),
+                   Ok(Command::Compile(Args {
+            inputs: vec![PathBuf::from("A.jack")],
+            warn_shadowing: true,
+            strict_types: true,
+            ..Args::default()
+        })));
+        assert_eq!(parse(&["A.jack", "--emit", "asm", "-o", "out", "B.jack", "-q"]),
+                   Ok(Command::Compile(Args {
+                       inputs: vec![PathBuf::from("A.jack"), PathBuf::from("B.jack")],
+                       emit: Some(Emit::Asm),
+                       output: Some(PathBuf::from("out")),
+                       quiet: true,
+                       ..Args::default()
+                   })));
+        assert_eq!(parse(&["--emit=ast-json", "--max-errors=3", "-"]),
+                   Ok(Command::Compile(Args {
+                       inputs: vec![PathBuf::from("-")],
+                       emit: Some(Emit::AstJson),
+                       max_errors: Some(3),
+                       ..Args::default()
+                   })));
+        assert_eq!(parse(&["A.jack", "--help"]), Ok(Command::Help));
+        assert_eq!(parse(&["-V"]), Ok(Command::Version));
+
+        assert_eq!(parse(&["--lang", "ja", "A.jack"]), Ok(Command::Compile(Args {
+            inputs: vec![PathBuf::from("A.jack")],
+            ..Args::default()
+        })));
+
+        assert_eq!(parse(&[]), Err(Code::NoInput));
+        assert_eq!(parse(&["A.jack", "--emit", "exe"]), Err(Code::InvalidEmit));
+        assert_eq!(parse(&["A.jack", "--out-dir"]), Err(Code::MissingValue));
+        assert_eq!(parse(&["A.jack", "--max-errors", "x"]), Err(Code::InvalidMaxErrors));
+        assert_eq!(parse(&["A.jack", "--max-errors=0"]), Err(Code::InvalidMaxErrors));
+        assert_eq!(parse(&["A.jack", "--unknown"]), Err(Code::UnknownOption));
+        assert_eq!(parse(&["A.jack", "-o", "a", "--out-dir", "b"]), Err(Code::OutputConflict));
+        assert_eq!(parse(&["A.jack", "--lang=fr"]), Err(Code::InvalidLang));
+    }
+
+    #[test]
+    fn test_select_lang() {
+        assert_eq!(select_lang(&strings(&["A.jack"]), None), Lang::En);
+        assert_eq!(select_lang(&strings(&["A.jack"]), Some("ja_JP.UTF-8")), Lang::Ja);
+        // --langはLANGより優先し、後に書いたものを使う
+        assert_eq!(select_lang(&strings(&["--lang", "ja", "--bad"]), Some("C")), Lang::Ja);
+        assert_eq!(select_lang(&strings(&["--lang=ja", "--lang=en"]), None), Lang::En);
+        assert_eq!(select_lang(&strings(&["--lang=fr"]), Some("ja")), Lang::Ja);
+        assert_eq!(select_lang(&strings(&["--language"]), None), Lang::En);
+    }
+
+    #[test]
+    fn test_output_path() {
+        let input = Path::new("src/Main.jack");
+        assert_eq!(output_path(input, Emit::Vm, None, None), Ok(PathBuf::from("src/Main.vm")));
+        assert_eq!(output_path(input, Emit::Xml, None, Some(Path::new("out"))),
+                   Ok(PathBuf::from("out/Main.xml")));
+        assert_eq!(output_path(input, Emit::Vm, Some(Path::new("a.vm")), None),
+                   Ok(PathBuf::from("a.vm")));
+
+        // 標準入力のときは標準出力に書き出す
+        let stdin = Path::new("-");
+        assert_eq!(output_path(stdin, Emit::Vm, None, None), Ok(PathBuf::from("-")));
+        assert_eq!(output_path(stdin, Emit::Vm, None, Some(Path::new("out"))).map_err(|e| e.code),
+                   Err(Code::StdinOutDir));
+    }
+
+    #[test]
+    fn test_program_path() {
+        let inputs = [PathBuf::from("src/Main.jack"), PathBuf::from("src/Game.jack")];
+        assert_eq!(program_path(&inputs, &inputs, None, None), Ok(PathBuf::from("src/Main.asm")));
+        assert_eq!(program_path(&inputs, &inputs, Some(Path::new("a.asm")), None),
+                   Ok(PathBuf::from("a.asm")));
+
+        // ディレクトリを指定したときはディレクトリの名前にする
+        let dir = env::temp_dir().join("compiler_test_program_path").join("Pong");
+        fs::create_dir_all(&dir).unwrap();
+        let args = [dir.clone()];
+        assert_eq!(program_path(&args, &inputs, None, None), Ok(dir.join("Pong.asm")));
+        assert_eq!(program_path(&args, &inputs, None, Some(Path::new("out"))),
+                   Ok(PathBuf::from("out/Pong.asm")));
+    }
+
+    #[test]
+    fn test_output_target() {
+        let file = |p: &str| (Some(PathBuf::from(p)), None);
+        let dir = |p: &str| (None, Some(PathBuf::from(p)));
+        assert_eq!(output_target(Some(PathBuf::from("a.vm")), None, true), file("a.vm"));
+        assert_eq!(output_target(Some(PathBuf::from("out")), None, false), dir("out"));
+        assert_eq!(output_target(None, Some(PathBuf::from("out")), true), dir("out"));
+        assert_eq!(output_target(None, None, false), (None, None));
+
+        // -o -は入力が複数でも標準出力
+        assert_eq!(output_target(Some(PathBuf::from("-")), None, false), file("-"));
+    }
+
+    // テスト用のディレクトリを空にして作る
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_jack_files() {
+        let dir = temp_dir("compiler_test_jack_files");
+        fs::write(dir.join("B.jack"), "").unwrap();
+        fs::write(dir.join("A.jack"), "").unwrap();
+        fs::write(dir.join("note.txt"), "").unwrap();
+        fs::create_dir(dir.join("C.jack")).unwrap();
+
+        // jackファイルだけを名前順に返す
+        assert_eq!(jack_files(&dir).unwrap(), vec![dir.join("A.jack"), dir.join("B.jack")]);
+        assert!(jack_files(&dir.join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_run() {
+        let dir = temp_dir("compiler_test_run");
+        let src = dir.join("src");
+        fs::create_dir(&src).unwrap();
+        fs::write(src.join("Good.jack"), "class Good { function void f() { return; } }").unwrap();
+        fs::write(src.join("Bad.jack"), "class Bad { function void f() { return } }").unwrap();
+        let out = dir.join("out");
+        let args = |inputs: Vec<PathBuf>| Args {
+            inputs, out_dir: Some(out.clone()), quiet: true, ..Args::default()
+        };
+
+        // ディレクトリを展開し、失敗したファイルがあってもほかは書き出す
+        let summary = run(args(vec![src.clone()]), Lang::En).unwrap();
+        assert_eq!(summary, Summary { total: 2, failures: 1, worst: Some(Failure::Compile) });
+        assert!(out.join("Good.vm").is_file());
+        assert!(!out.join("Bad.vm").exists());
+
+        // 読めないファイルがあるときは入出力のエラーを優先する
+        let summary = run(args(vec![src.clone(), dir.join("Missing.jack")]), Lang::En).unwrap();
+        assert_eq!(summary, Summary { total: 3, failures: 2, worst: Some(Failure::Io) });
+
+        // jackファイルのないディレクトリはコンパイルする前に失敗する
+        fs::create_dir(out.join("empty")).unwrap();
+        assert_eq!(run(args(vec![out.join("empty")]), Lang::En), Err(Failure::Io));
+
+        fs::remove_file(src.join("Bad.jack")).unwrap();
+        let summary = run(args(vec![src]), Lang::En).unwrap();
+        assert_eq!(summary, Summary { total: 1, failures: 0, worst: None });
+    }
+
+    #[test]
+    fn test_run_asm() {
+        let dir = temp_dir("compiler_test_run_asm");
+        fs::write(dir.join("Main.jack"), "class Main { function void main() { return; } }").unwrap();
+        // 前にコンパイルしたMain.vmはリンクしない
+        fs::write(dir.join("Main.vm"), "function Main.main 0\n").unwrap();
+        let args = || Args { inputs: vec![dir.clone()], emit: Some(Emit::Asm), quiet: true,
+                             ..Args::default() };
+        let program = dir.join(format!("{}.asm", dir.file_name().unwrap().to_string_lossy()));
+
+        // Sys.initがどこにもなければプログラムを書かずに失敗する
+        assert_eq!(library_files(&dir).unwrap(), Vec::<PathBuf>::new());
+        let summary = run(args(), Lang::En).unwrap();
+        assert_eq!(summary.worst, Some(Failure::Compile));
+        assert!(!program.exists());
+
+        // ディレクトリにあるOSのvmファイルを一緒につなげる
+        fs::write(dir.join("Sys.vm"), "function Sys.init 0\ncall Main.main 0\n\
+                                       label L\ngoto L\n").unwrap();
+        assert_eq!(library_files(&dir).unwrap(), vec![dir.join("Sys.vm")]);
+        let summary = run(args(), Lang::En).unwrap();
+        assert_eq!(summary, Summary { total: 1, failures: 0, worst: None });
+        let asm = fs::read_to_string(&program).unwrap();
+        assert!(asm.contains("(Main.main)\n") && asm.contains("(Sys.init)\n"));
+    }
+
+    #[test]
+    fn test_exit_code() {
+        // コンパイルのエラー、引数の誤り、入出力のエラーで終了コードを分ける
+        assert_eq!(Failure::Compile.exit_code(), 1);
+        assert_eq!(EXIT_USAGE, 2);
+        assert_eq!(Failure::Io.exit_code(), 3);
+        assert_eq!(Some(Failure::Compile).max(Some(Failure::Io)), Some(Failure::Io));
 
-    c.tokenizer.advance();
-    match c.compile_class() {
-        Ok(()) => (),
-        Err(e) => println!("error: {}", e)
+        // 入力を読み出せなかったときは入出力のエラーにする
+        let options = compiler::Options::default();
+        let result = compiler::compile_str("class A {", &options);
+        assert_eq!(take_output(result, true), Err(Failure::Compile));
+        let io = CompileError::io("<stdin>", &io::Error::other("broken pipe"));
+        let result = Err(vec![Diagnostic::new(io, "")]);
+        assert_eq!(take_output(result, true), Err(Failure::Io));
     }
 }
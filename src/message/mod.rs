@@ -0,0 +1,458 @@
+//! 利用者に見せる文の一覧。
+//! 文はCodeごとに英語と日本語を持ち、{0}や{1}の部分を引数で置き換えて使う。
+//! 言語は--langか環境変数LANGで選ぶ
+
+use std::fmt;
+
+
+/// 表示に使う言語
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Lang {
+    #[default]
+    En,
+    Ja,
+}
+
+impl Lang {
+    /// enかjaの名前から言語を返す
+    pub fn from_name(name: &str) -> Option<Lang> {
+        match name {
+            "en" => Some(Lang::En),
+            "ja" => Some(Lang::Ja),
+            _ => None
+        }
+    }
+
+    /// ja_JP.UTF-8のようなロケールの名前から言語を返す。
+    /// jaで始まらないものは全て英語にする
+    pub fn from_locale(locale: &str) -> Lang {
+        if locale.starts_with("ja") {
+            Lang::Ja
+        } else {
+            Lang::En
+        }
+    }
+}
+
+
+// 文の種類を宣言し、全ての種類を並べたCode::ALLも作る。
+// 種類を足せばALLにも必ず入るので、test_code_translationsで全ての種類の訳を調べられる
+macro_rules! codes {
+    ($(#[$meta:meta])* pub enum $name:ident { $($variant:ident,)* }) => {
+        $(#[$meta])*
+        pub enum $name {
+            $($variant,)*
+        }
+
+        impl $name {
+            /// 全ての種類
+            pub const ALL: &'static [$name] = &[$($name::$variant,)*];
+        }
+    };
+}
+
+codes! {
+    /// 文の種類。Eはコンパイルのエラー、Cはコマンドラインの文、
+    /// Nは診断に付ける文を表す
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Code {
+        UnexpectedToken,
+        UnexpectedEof,
+        UnterminatedString,
+        UnterminatedComment,
+        InvalidUtf8,
+        UnknownCharacter,
+        IntegerOverflow,
+        UndefinedVariable,
+        Io,
+        IoNotFound,
+        IoPermissionDenied,
+        Redeclared,
+        Shadowed,
+        TypeMismatch,
+        NotAnArray,
+        MethodOnPrimitive,
+        UnknownSubroutine,
+        ArgumentCount,
+        MethodCalledAsFunction,
+        FunctionCalledAsMethod,
+        InvalidVm,
+        UndefinedFunction,
+        FieldInFunction,
+
+        Error,
+        Warning,
+        ExpectedOne,
+        ExpectedOneOf,
+        DidYouMean,
+        Note,
+        Hint,
+
+        Help,
+        MissingValue,
+        InvalidEmit,
+        InvalidMaxErrors,
+        InvalidLang,
+        UnknownOption,
+        NoInput,
+        OutputConflict,
+        StdinOutDir,
+        NoJackFiles,
+        Summary,
+        SeeHelp,
+    }
+}
+
+impl Code {
+    /// E0001のような番号を返す
+    pub fn id(self) -> &'static str {
+        match self {
+            Code::UnexpectedToken => "E0001",
+            Code::UnexpectedEof => "E0002",
+            Code::UnterminatedString => "E0003",
+            Code::UnterminatedComment => "E0004",
+            Code::InvalidUtf8 => "E0005",
+            Code::UnknownCharacter => "E0006",
+            Code::IntegerOverflow => "E0007",
+            Code::UndefinedVariable => "E0008",
+            Code::Io => "E0009",
+            Code::IoNotFound => "E0010",
+            Code::IoPermissionDenied => "E0011",
+            Code::Redeclared => "E0012",
+            Code::Shadowed => "E0013",
+            Code::TypeMismatch => "E0014",
+            Code::NotAnArray => "E0015",
+            Code::MethodOnPrimitive => "E0016",
+            Code::UnknownSubroutine => "E0017",
+            Code::ArgumentCount => "E0018",
+            Code::MethodCalledAsFunction => "E0019",
+            Code::FunctionCalledAsMethod => "E0020",
+            Code::InvalidVm => "E0021",
+            Code::UndefinedFunction => "E0022",
+            Code::FieldInFunction => "E0023",
+            Code::Error => "N0001",
+            Code::Warning => "N0002",
+            Code::ExpectedOne => "N0003",
+            Code::ExpectedOneOf => "N0004",
+            Code::DidYouMean => "N0005",
+            Code::Note => "N0006",
+            Code::Hint => "N0007",
+            Code::Help => "C0001",
+            Code::MissingValue => "C0002",
+            Code::InvalidEmit => "C0003",
+            Code::InvalidMaxErrors => "C0004",
+            Code::InvalidLang => "C0005",
+            Code::UnknownOption => "C0006",
+            Code::NoInput => "C0007",
+            Code::OutputConflict => "C0008",
+            Code::StdinOutDir => "C0009",
+            Code::NoJackFiles => "C0010",
+            Code::Summary => "C0011",
+            Code::SeeHelp => "C0012",
+        }
+    }
+
+    /// 置き換える前の文を返す
+    pub fn template(self, lang: Lang) -> &'static str {
+        let (en, ja) = match self {
+            Code::UnexpectedToken => (
+                "unexpected token: '{0}'",
+                "予期しないトークンです: '{0}'"),
+            // 英語はjavacのエラー文を流用
+            Code::UnexpectedEof => (
+                "reached end of file while parsing",
+                "構文の途中でファイルが終わりました"),
+            Code::UnterminatedString => (
+                "unclosed string literal",
+                "文字列が閉じられていません"),
+            Code::UnterminatedComment => (
+                "unclosed comment",
+                "コメントが閉じられていません"),
+            Code::InvalidUtf8 => (
+                "stream did not contain valid UTF-8",
+                "UTF-8として正しくないバイトがあります"),
+            Code::UnknownCharacter => (
+                "unknown start of token: {0}",
+                "トークンの始まりにならない文字です: {0}"),
+            Code::IntegerOverflow => (
+                "integer number too large: {0}",
+                "整数が大きすぎます: {0}"),
+            Code::UndefinedVariable => (
+                "undefined variable: '{0}'",
+                "宣言されていない変数です: '{0}'"),
+            Code::Io => ("{0}: {1}", "{0}: {1}"),
+            Code::IoNotFound => (
+                "file not found",
+                "ファイルが見つかりません"),
+            Code::IoPermissionDenied => (
+                "permission denied",
+                "アクセスが拒否されました"),
+            Code::Redeclared => (
+                "'{0}' is already declared at {1}",
+                "'{0}'は{1}で宣言済みです"),
+            Code::Shadowed => (
+                "'{0}' shadows the class variable declared at {1}",
+                "'{0}'は{1}で宣言したクラスの変数を隠しています"),
+            Code::TypeMismatch => (
+                "mismatched types: expected {0}, found {1}",
+                "型が一致しません: {0}が必要ですが{1}です"),
+            Code::NotAnArray => (
+                "cannot index '{0}' of type {1}",
+                "{1}型の'{0}'は添字で参照できません"),
+            Code::MethodOnPrimitive => (
+                "cannot call a method on '{0}' of type {1}",
+                "{1}型の'{0}'のメソッドは呼び出せません"),
+            Code::UnknownSubroutine => (
+                "unknown subroutine: '{0}'",
+                "宣言されていないサブルーチンです: '{0}'"),
+            Code::ArgumentCount => (
+                "'{0}' takes {1} arguments but {2} were given",
+                "'{0}'の引数は{1}個ですが{2}個渡されています"),
+            Code::MethodCalledAsFunction => (
+                "'{0}' is a method and must be called on an object",
+                "'{0}'はメソッドなのでオブジェクトに対して呼び出してください"),
+            Code::FunctionCalledAsMethod => (
+                "'{0}' is a {1}, not a method",
+                "'{0}'は{1}なのでメソッドとして呼び出せません"),
+            Code::InvalidVm => (
+                "cannot translate line {0} of the vm code: {1}",
+                "vmコードの{0}行目を変換できません: {1}"),
+            Code::UndefinedFunction => (
+                "'{0}' is called but no linked file defines it",
+                "'{0}'を呼び出していますが、どのファイルにも定義されていません"),
+            Code::FieldInFunction => (
+                "field '{0}' cannot be used in a function",
+                "関数ではフィールド'{0}'を参照できません"),
+
+            Code::Error => ("error", "エラー"),
+            Code::Warning => ("warning", "警告"),
+            Code::ExpectedOne => ("expected {0}", "{0}が必要です"),
+            Code::ExpectedOneOf => (
+                "expected one of: {0}",
+                "次のいずれかが必要です: {0}"),
+            Code::DidYouMean => ("did you mean `{0}`?", "`{0}`のことですか?"),
+            Code::Note => ("note", "注"),
+            Code::Hint => ("help", "ヒント"),
+
+            Code::Help => (HELP_EN, HELP_JA),
+            Code::MissingValue => (
+                "{0} requires a value",
+                "{0}には値を指定してください"),
+            Code::InvalidEmit => (
+                "--emit must be one of tokens, xml, vm, asm, ast-json",
+                "出力の形式はtokens、xml、vm、asm、ast-jsonのいずれかを指定してください"),
+            Code::InvalidMaxErrors => (
+                "--max-errors requires a number of at least 1",
+                "--max-errorsには1以上の数を指定してください"),
+            Code::InvalidLang => (
+                "--lang must be en or ja",
+                "--langにはenかjaを指定してください"),
+            Code::UnknownOption => (
+                "unknown option: {0}",
+                "不明なオプションです: {0}"),
+            Code::NoInput => (
+                "no input files",
+                "ファイル名を指定してください"),
+            Code::OutputConflict => (
+                "-o and --out-dir cannot be used together",
+                "-oと--out-dirは同時に指定できません"),
+            Code::StdinOutDir => (
+                "use -o to name the output file when reading from standard input",
+                "標準入力から読むときは-oで出力するファイル名を指定してください"),
+            Code::NoJackFiles => (
+                "{0}: no jack files found",
+                "{0}: jackファイルが見つかりません"),
+            Code::Summary => (
+                "compiled {0} files ({1} succeeded, {2} failed)",
+                "{0}個のファイルをコンパイルしました (成功: {1}, 失敗: {2})"),
+            Code::SeeHelp => (
+                "see --help for more information",
+                "詳しくは--helpを参照してください"),
+        };
+
+        match lang {
+            Lang::En => en,
+            Lang::Ja => ja,
+        }
+    }
+
+    /// {0}、{1}...をargsで置き換えた文を返す
+    pub fn format(self, lang: Lang, args: &[&str]) -> String {
+        let template = self.template(lang);
+        let mut s = String::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            s += &rest[..start];
+            let after = &rest[start + 1..];
+            let index = after.find('}')
+                .and_then(|end| after[..end].parse::<usize>().ok().map(|i| (i, end)));
+            match index {
+                Some((i, end)) if i < args.len() => {
+                    s += args[i];
+                    rest = &after[end + 1..];
+                },
+                // 置き換えられないものはそのまま残す
+                _ => {
+                    s.push('{');
+                    rest = after;
+                },
+            }
+        }
+        s + rest
+    }
+}
+
+
+/// 後で言語を決めて表示する文
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub code: Code,
+    pub args: Vec<String>,
+}
+
+impl Message {
+    pub fn new(code: Code, args: &[&str]) -> Message {
+        Message { code, args: args.iter().map(|a| a.to_string()).collect() }
+    }
+
+    /// langの言語にした文を返す
+    pub fn text(&self, lang: Lang) -> String {
+        let args: Vec<&str> = self.args.iter().map(String::as_str).collect();
+        self.code.format(lang, &args)
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text(Lang::En))
+    }
+}
+
+
+const HELP_EN: &str = "\
+Usage: compiler [options] <input>...
+
+Each input is a jack file or a directory containing jack files.
+Use - to read from standard input and write to standard output.
+
+Options:
+  --emit <format>          output format (tokens, xml, vm, asm, ast-json)
+                           defaults to the extension of -o (T.xml is
+                           tokens), or vm
+                           asm links all inputs into one program that
+                           starts with bootstrap code calling Sys.init,
+                           together with the OS .vm files in input
+                           directories; every called function must exist
+  -o <path>                output file, or a directory for multiple inputs
+                           use - to write to standard output
+  --out-dir <dir>          directory to write outputs to
+  --max-errors <n>         maximum number of errors reported per file
+  --lenient                skip unknown characters with a warning
+  --warnings-as-errors     treat warnings as errors
+  --warn-shadowing         warn when a local hides a field or static
+  --strict-types           report type errors such as assigning a String to an int
+  --lang <en|ja>           language of messages (defaults to LANG)
+  -q, --quiet              do not print warnings or the summary
+  -h, --help               print this help
+  -V, --version            print the version
+
+Exit codes:
+  0  success
+  1  compile error
+  2  invalid arguments
+  3  I/O error
+";
+
+const HELP_JA: &str = "\
+使い方: compiler [オプション] <入力>...
+
+入力にはjackファイルか、jackファイルのあるディレクトリを指定する。
+-を指定すると標準入力から読み、標準出力に書き出す
+
+オプション:
+  --emit <形式>            出力の形式 (tokens, xml, vm, asm, ast-json)
+                           省略したときは-oの拡張子から決め(T.xmlはtokens)、
+                           それもなければvm
+                           asmは全ての入力と入力のディレクトリにあるOSの
+                           vmファイルを、Sys.initを呼ぶブートストラップの
+                           コードから始まる1つのプログラムにする。
+                           呼び出す関数は全て定義されていなければならない
+  -o <パス>                出力するファイル。入力が複数のときはディレクトリ
+                           -を指定すると標準出力に書き出す
+  --out-dir <ディレクトリ>   出力を書き込むディレクトリ
+  --max-errors <数>        1つのファイルで報告するエラーの最大数
+  --lenient                知らない文字をエラーにせず、警告にして読み飛ばす
+  --warnings-as-errors     警告をエラーとして扱う
+  --warn-shadowing         サブルーチンの変数がクラスの変数を隠すときに警告する
+  --strict-types           Stringをintに代入するといった型の誤りを報告する
+  --lang <en|ja>           表示する言語 (省略したときはLANGから決める)
+  -q, --quiet              警告と結果の要約を表示しない
+  -h, --help               この説明を表示する
+  -V, --version            バージョンを表示する
+
+終了コード:
+  0  成功
+  1  コンパイルエラー
+  2  引数の誤り
+  3  入出力のエラー
+";
+
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, HashSet};
+
+    use super::{Code, Lang, Message};
+
+    // 文に含まれる{0}、{1}...の番号
+    fn placeholders(template: &str) -> HashSet<usize> {
+        template.split('{').skip(1)
+            .filter_map(|s| s.split('}').next()?.parse().ok())
+            .collect()
+    }
+
+    #[test]
+    fn test_code_translations() {
+        let mut ids = HashSet::new();
+        let mut counts = HashMap::new();
+        for &code in Code::ALL {
+            assert!(ids.insert(code.id()), "{:?}の番号が重複している", code);
+            // 番号は種類ごとに宣言の順に1から振る
+            let (kind, number) = code.id().split_at(1);
+            let count = counts.entry(kind).or_insert(0);
+            *count += 1;
+            assert_eq!(number.parse(), Ok(*count), "{:?}の番号が順に並んでいない", code);
+
+            // 全ての種類に英語と日本語があり、置き換える部分が同じ
+            let en = code.template(Lang::En);
+            let ja = code.template(Lang::Ja);
+            assert!(!en.is_empty() && !ja.is_empty(), "{:?}の訳がない", code);
+            assert!(en.is_ascii(), "{:?}の英語に英語以外の文字がある", code);
+            assert_eq!(placeholders(en), placeholders(ja), "{:?}", code);
+        }
+    }
+
+    #[test]
+    fn test_code_format() {
+        assert_eq!(Code::Summary.format(Lang::En, &["3", "2", "1"]),
+                   "compiled 3 files (2 succeeded, 1 failed)");
+        assert_eq!(Code::Summary.format(Lang::Ja, &["3", "2", "1"]),
+                   "3個のファイルをコンパイルしました (成功: 2, 失敗: 1)");
+        // 引数の中の{0}は置き換えない
+        assert_eq!(Code::UnknownOption.format(Lang::En, &["{0}"]), "unknown option: {0}");
+        assert_eq!(Code::UnknownOption.format(Lang::En, &[]), "unknown option: {0}");
+
+        let m = Message::new(Code::MissingValue, &["-o"]);
+        assert_eq!(m.text(Lang::Ja), "-oには値を指定してください");
+        assert_eq!(m.to_string(), "-o requires a value");
+    }
+
+    #[test]
+    fn test_lang() {
+        assert_eq!(Lang::from_name("ja"), Some(Lang::Ja));
+        assert_eq!(Lang::from_name("fr"), None);
+        assert_eq!(Lang::from_locale("ja_JP.UTF-8"), Lang::Ja);
+        assert_eq!(Lang::from_locale("en_US.UTF-8"), Lang::En);
+        assert_eq!(Lang::from_locale("C"), Lang::En);
+    }
+}
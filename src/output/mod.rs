@@ -0,0 +1,172 @@
+//! 出力の書き込み。
+//! CheckedWriterは最初に起きた入出力のエラーを覚えておき、書き終えた後に
+//! まとめて返す。write_fileは一時ファイルに書いてから名前を変えるので、
+//! 失敗しても途中まで書いたファイルが残らない
+
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use super::error::CompileError;
+
+
+/// 書き込みのエラーを捨てずに保持するWriter。
+/// 書き込みは全てwrite_allで行い、エラーが起きた後は何も書かない
+pub struct CheckedWriter<W> {
+    inner: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> CheckedWriter<W> {
+    pub fn new(inner: W) -> CheckedWriter<W> {
+        CheckedWriter { inner, error: None }
+    }
+
+    /// 1行書く。エラーはfinishで返す
+    pub fn write_line(&mut self, line: fmt::Arguments) {
+        if self.error.is_none() {
+            let result = self.inner.write_fmt(line).and_then(|()| self.inner.write_all(b"\n"));
+            if let Err(e) = result {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    /// 書き込みを終える。途中でエラーが起きていればそれを返す
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e)
+        }
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Write for CheckedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.error.is_none() {
+            if let Err(e) = self.inner.write_all(buf) {
+                self.error = Some(e);
+            }
+        }
+        // エラーはfinishで返すので、ここでは書き込めたことにする
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.error.is_none() {
+            if let Err(e) = self.inner.flush() {
+                self.error = Some(e);
+            }
+        }
+        // writeと同じく、エラーはfinishで返す
+        Ok(())
+    }
+}
+
+// pathと同じディレクトリに作る一時ファイルのパス
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}.tmp", name))
+}
+
+/// contentsを一時ファイルに書き、書き終えたらpathに名前を変える。
+/// 失敗したときは一時ファイルを消してエラーを返す
+pub fn write_file(path: &Path, contents: &[u8]) -> Result<(), CompileError> {
+    let temp = temp_path(path);
+    let result = File::create(&temp)
+        .and_then(|mut f| {
+            f.write_all(contents)?;
+            f.sync_all()
+        })
+        .and_then(|()| fs::rename(&temp, path));
+
+    result.map_err(|e| {
+        let _ = fs::remove_file(&temp);
+        CompileError::io(&path.display().to_string(), &e)
+    })
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::io;
+    use std::io::Write;
+
+    use super::CheckedWriter;
+    use super::write_file;
+    use super::temp_path;
+
+    // 2バイトより多くは書けないWriter
+    struct Full(Vec<u8>);
+
+    impl Write for Full {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if 2 <= self.0.len() {
+                return Err(io::Error::other("disk full"))
+            }
+            self.0.push(buf[0]);
+            Ok(1)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // 書き出せないWriter
+    struct Closed;
+
+    impl Write for Closed {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Err(io::Error::other("closed"))
+        }
+    }
+
+    #[test]
+    fn test_checked_writer() {
+        let mut output = Vec::new();
+        let mut w = CheckedWriter::new(&mut output);
+        writeln!(w, "push constant {}", 1).unwrap();
+        assert!(w.finish().is_ok());
+        assert_eq!(output, b"push constant 1\n");
+
+        let mut w = CheckedWriter::new(Full(Vec::new()));
+        w.write_line(format_args!("return"));
+        w.write_line(format_args!("return"));
+        assert_eq!(w.finish().unwrap_err().to_string(), "disk full");
+
+        // flushのエラーも覚えておく
+        let mut w = CheckedWriter::new(Closed);
+        w.write_line(format_args!("return"));
+        assert!(w.flush().is_ok());
+        assert_eq!(w.error.as_ref().map(|e| e.to_string()).as_deref(), Some("closed"));
+        assert_eq!(w.finish().unwrap_err().to_string(), "closed");
+    }
+
+    #[test]
+    fn test_write_file() {
+        let dir = env::temp_dir().join(format!("jack_output_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Main.vm");
+
+        assert_eq!(write_file(&path, b"return\n"), Ok(()));
+        assert_eq!(fs::read(&path).unwrap(), b"return\n");
+        assert!(!temp_path(&path).exists());
+
+        // 書き込めないときは一時ファイルを残さない
+        let missing = dir.join("missing").join("Main.vm");
+        assert!(write_file(&missing, b"return\n").is_err());
+        assert!(!temp_path(&missing).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,244 @@
+//! Resolverは構文木の変数の参照をSymbolTableで解決する。
+//! 宣言されていない変数や同じスコープでの再宣言を全て集めて返す。
+//! ドットの前の名前は、変数として宣言されていなければ大文字で始まるときだけ
+//! クラス名として扱う。関数の中でのフィールドの参照もエラーにする
+
+use std::mem;
+
+use super::ast::*;
+use super::symbol_table::{Kind, SymbolTable};
+use super::error::CompileError;
+
+
+#[derive(Default)]
+pub struct Resolver {
+    symbol_table: SymbolTable,
+    // サブルーチンの変数がクラスの変数を隠すときに警告するか
+    warn_shadowing: bool,
+    // 解決しているサブルーチンが関数か
+    in_function: bool,
+    errors: Vec<CompileError>,
+    warnings: Vec<CompileError>,
+}
+
+// クラス名として扱う名前か。Jackのクラス名は大文字で始める
+fn is_class_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_uppercase())
+}
+
+impl Resolver {
+    pub fn new() -> Resolver {
+        Resolver::default()
+    }
+
+    /// trueにするとサブルーチンの変数や引数がクラスの変数を隠すときに警告する
+    pub fn set_warn_shadowing(&mut self, warn_shadowing: bool) {
+        self.warn_shadowing = warn_shadowing;
+    }
+
+    /// これまでの警告を取り出す
+    pub fn take_warnings(&mut self) -> Vec<CompileError> {
+        mem::take(&mut self.warnings)
+    }
+
+    // 変数の参照を解決する
+    fn resolve_variable(&mut self, name: &Identifier) {
+        match self.symbol_table.kind_of(&name.name) {
+            None => self.errors.push(CompileError::UndefinedVariable {
+                name: name.name.clone(),
+                span: name.span.clone(),
+            }),
+            // 関数にはthisがないのでフィールドは参照できない
+            Some(Kind::Field) if self.in_function => {
+                self.errors.push(CompileError::FieldInFunction {
+                    name: name.name.clone(),
+                    span: name.span.clone(),
+                })
+            },
+            Some(_) => (),
+        }
+    }
+
+    /// クラスの全ての変数の参照を解決する。エラーがあればソースコードの順に返す
+    pub fn resolve_class(&mut self, class: &Class) -> Result<(), Vec<CompileError>> {
+        self.symbol_table = SymbolTable::new();
+        self.symbol_table.set_warn_shadowing(self.warn_shadowing);
+
+        self.errors = self.symbol_table.define_class_vars(class);
+        for subroutine_dec in &class.subroutine_decs {
+            let mut errors = self.symbol_table.enter_subroutine(&class.name.name, subroutine_dec);
+            self.errors.append(&mut errors);
+            self.in_function = subroutine_dec.kind == SubroutineKind::Function;
+            self.resolve_statements(&subroutine_dec.statements);
+        }
+        self.warnings.append(&mut self.symbol_table.take_warnings());
+
+        if self.errors.is_empty() {
+            return Ok(())
+        }
+        let mut errors = mem::take(&mut self.errors);
+        errors.sort_by_key(|e| e.span().map(|s| s.byte_range.start));
+        Err(errors)
+    }
+
+    fn resolve_statements(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.resolve_statement(statement);
+        }
+    }
+
+    fn resolve_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Let { name, index, value, .. } => {
+                self.resolve_variable(name);
+                if let Some(index) = index {
+                    self.resolve_expression(index);
+                }
+                self.resolve_expression(value);
+            },
+            Statement::If { condition, then_statements, else_statements, .. } => {
+                self.resolve_expression(condition);
+                self.resolve_statements(then_statements);
+                if let Some(else_statements) = else_statements {
+                    self.resolve_statements(else_statements);
+                }
+            },
+            Statement::While { condition, statements, .. } => {
+                self.resolve_expression(condition);
+                self.resolve_statements(statements);
+            },
+            Statement::Do { call, .. } => self.resolve_subroutine_call(call),
+            Statement::Return { value, .. } => {
+                if let Some(value) = value {
+                    self.resolve_expression(value);
+                }
+            },
+        }
+    }
+
+    fn resolve_expression(&mut self, expression: &Expression) {
+        self.resolve_term(&expression.term);
+        for (_, term) in &expression.rest {
+            self.resolve_term(term);
+        }
+    }
+
+    fn resolve_term(&mut self, term: &Term) {
+        match term {
+            Term::IntegerConstant { .. } |
+            Term::StringConstant { .. } |
+            Term::KeywordConstant { .. } => (),
+            Term::VarName(name) => self.resolve_variable(name),
+            Term::ArrayAccess { name, index, .. } => {
+                self.resolve_variable(name);
+                self.resolve_expression(index);
+            },
+            Term::SubroutineCall(call) => self.resolve_subroutine_call(call),
+            Term::Parenthesized { expression, .. } => self.resolve_expression(expression),
+            Term::Unary { term, .. } => self.resolve_term(term),
+        }
+    }
+
+    fn resolve_subroutine_call(&mut self, call: &SubroutineCall) {
+        if let Some(receiver) = &call.receiver {
+            // 宣言された変数がなく、大文字で始まる名前はクラス名
+            if self.symbol_table.lookup(&receiver.name).is_some() ||
+               !is_class_name(&receiver.name) {
+                self.resolve_variable(receiver);
+            }
+        }
+        for argument in &call.arguments {
+            self.resolve_expression(argument);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::Resolver;
+    use crate::error::CompileError;
+    use crate::compilation_engine::parse_str;
+
+    // エラーの名前と行番号の一覧
+    fn errors(source: &str) -> Vec<(String, usize)> {
+        let result = Resolver::new().resolve_class(&parse_str(source).unwrap());
+        result.err().unwrap_or_default().iter().map(|e| match e {
+            CompileError::UndefinedVariable { name, span } |
+            CompileError::FieldInFunction { name, span } |
+            CompileError::Redeclared { name, span, .. } => (name.clone(), span.start_line),
+            e => panic!("{:?}", e)
+        }).collect()
+    }
+
+    #[test]
+    fn test_resolve_class() {
+        assert_eq!(errors("class A {
+            field Array a;
+            method void f(int i) {
+                var Game game;
+                let a[i] = Math.max(i, a[0]);
+                do game.run(this);
+                do Output.printInt(i);
+                return;
+            }
+        }"), Vec::new());
+
+        // 宣言されていない変数を全て報告する
+        assert_eq!(errors("class A {
+            function int f(int n) {
+                var int n;
+                let x = y[n] + (-z);
+                if (w) { do g(v); } else { do output.print(u); }
+                while (t) { return s; }
+                return Math.abs(r);
+            }
+        }"), vec![
+            ("n".to_string(), 3), ("x".to_string(), 4), ("y".to_string(), 4),
+            ("z".to_string(), 4), ("w".to_string(), 5), ("v".to_string(), 5),
+            ("output".to_string(), 5), ("u".to_string(), 5), ("t".to_string(), 6),
+            ("s".to_string(), 6), ("r".to_string(), 7),
+        ]);
+
+        // ローカル変数はサブルーチンごとに別
+        assert_eq!(errors("class A {
+            function void f() { var int i; return; }
+            function int g() { return i; }
+        }"), vec![("i".to_string(), 3)]);
+
+        // 関数ではフィールドを参照できないが、スタティック変数は参照できる
+        let source = "class A {
+            field Game game;
+            field int x;
+            static int s;
+            function void f() {
+                let x = s;
+                do game.run();
+                return;
+            }
+            method void g() { let x = s; do game.run(); return; }
+            constructor A new() { let x = s; return this; }
+        }";
+        assert_eq!(errors(source), vec![("x".to_string(), 6), ("game".to_string(), 7)]);
+        let class = parse_str(source).unwrap();
+        match Resolver::new().resolve_class(&class).unwrap_err().as_slice() {
+            [CompileError::FieldInFunction { .. }, CompileError::FieldInFunction { .. }] => (),
+            e => panic!("{:?}", e)
+        }
+    }
+
+    #[test]
+    fn test_resolve_warnings() {
+        let class = parse_str("class A { field int x; method void f(int x) { return; } }").unwrap();
+        let mut r = Resolver::new();
+        assert_eq!(r.resolve_class(&class), Ok(()));
+        assert!(r.take_warnings().is_empty());
+
+        r.set_warn_shadowing(true);
+        assert_eq!(r.resolve_class(&class), Ok(()));
+        match r.take_warnings().as_slice() {
+            [CompileError::Shadowed { name, .. }] => assert_eq!(name, "x"),
+            w => panic!("{:?}", w)
+        }
+    }
+}
@@ -0,0 +1,400 @@
+//! 全てのクラスのサブルーチンのシグネチャを集め、呼び出しを確かめる。
+//! 複数のファイルをコンパイルするときは、先に全てのファイルを構文解析して
+//! Registryにシグネチャを集めてから、それぞれのクラスの呼び出しを確かめる。
+//! Registryにないクラスの呼び出しは確かめない。
+//! Jack OSのクラスのシグネチャはos以下のjackファイルの宣言から集める
+
+use std::collections::HashMap;
+use std::mem;
+
+use super::ast::*;
+use super::symbol_table::SymbolTable;
+use super::error::CompileError;
+use super::compilation_engine::parse_str;
+
+
+/// Jack OSのクラスの宣言
+const OS_CLASSES: [&str; 8] = [
+    include_str!("os/Math.jack"),
+    include_str!("os/String.jack"),
+    include_str!("os/Array.jack"),
+    include_str!("os/Output.jack"),
+    include_str!("os/Screen.jack"),
+    include_str!("os/Keyboard.jack"),
+    include_str!("os/Memory.jack"),
+    include_str!("os/Sys.jack"),
+];
+
+// 名前の間の編集距離
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous + usize::from(ca != *cb);
+            previous = row[j + 1];
+            row[j + 1] = substitution.min(previous + 1).min(row[j] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+
+/// サブルーチンのシグネチャ
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    pub kind: SubroutineKind,
+    /// voidのときはNone
+    pub return_type: Option<Type>,
+    pub parameters: Vec<Type>,
+}
+
+/// クラス名とサブルーチン名からシグネチャを引く表
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+    classes: HashMap<String, HashMap<String, Signature>>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    /// Jack OSの全てのクラスのシグネチャを集めた表を返す。
+    /// 同じ名前のクラスを加えると、そのクラスのシグネチャで置き換える
+    pub fn with_os() -> Registry {
+        let mut registry = Registry::new();
+        for source in OS_CLASSES {
+            let class = parse_str(source).expect("OSのクラスの宣言は正しい");
+            registry.add_class(&class);
+        }
+        registry
+    }
+
+    /// 1つのクラスだけのシグネチャを集めた表を返す
+    pub fn from_class(class: &Class) -> Registry {
+        let mut registry = Registry::new();
+        registry.add_class(class);
+        registry
+    }
+
+    /// クラスの全てのサブルーチンのシグネチャを加える
+    pub fn add_class(&mut self, class: &Class) {
+        let subroutines = class.subroutine_decs.iter().map(|s| (s.name.name.clone(), Signature {
+            kind: s.kind,
+            return_type: s.return_type.clone(),
+            parameters: s.parameters.iter().map(|p| p.ty.clone()).collect(),
+        })).collect();
+        self.classes.insert(class.name.name.clone(), subroutines);
+    }
+
+    /// シグネチャを知っているクラスか
+    pub fn contains_class(&self, class_name: &str) -> bool {
+        self.classes.contains_key(class_name)
+    }
+
+    /// サブルーチンのシグネチャを返す
+    pub fn get(&self, class_name: &str, name: &str) -> Option<&Signature> {
+        self.classes.get(class_name)?.get(name)
+    }
+
+    /// クラスの全てのサブルーチンの名前を名前順で返す
+    pub fn names(&self, class_name: &str) -> Vec<&str> {
+        let mut names: Vec<&str> = match self.classes.get(class_name) {
+            Some(subroutines) => subroutines.keys().map(|n| n.as_str()).collect(),
+            None => Vec::new(),
+        };
+        names.sort();
+        names
+    }
+
+    /// nameに似た名前のサブルーチンを返す。大文字と小文字の違いか、
+    /// 2文字までの違いなら似ているとする
+    pub fn similar(&self, class_name: &str, name: &str) -> Option<&str> {
+        let names = self.names(class_name);
+        if let Some(n) = names.iter().find(|n| n.eq_ignore_ascii_case(name)) {
+            return Some(n)
+        }
+        names.into_iter()
+            .map(|n| (distance(n, name), n))
+            .filter(|(d, _)| *d <= 2 && *d < name.len())
+            .min_by_key(|(d, _)| *d)
+            .map(|(_, n)| n)
+    }
+}
+
+
+/// クラスの全ての呼び出しについて、サブルーチンがあるか、引数の数と
+/// 呼び出し方(関数かメソッドか)が合っているかを確かめる
+pub struct CallChecker<'a> {
+    registry: &'a Registry,
+    class: &'a Class,
+    symbol_table: SymbolTable,
+    // 確かめているサブルーチンの種類
+    subroutine_kind: SubroutineKind,
+    errors: Vec<CompileError>,
+}
+
+impl<'a> CallChecker<'a> {
+    pub fn new(registry: &'a Registry, class: &'a Class) -> CallChecker<'a> {
+        CallChecker {
+            registry,
+            class,
+            symbol_table: SymbolTable::new(),
+            subroutine_kind: SubroutineKind::Function,
+            errors: Vec::new(),
+        }
+    }
+
+    /// 呼び出しを確かめる。変数は全て解決できるものとする。
+    /// エラーがあればソースコードの順に返す
+    pub fn check_class(mut self) -> Result<(), Vec<CompileError>> {
+        let class = self.class;
+        self.symbol_table.define_class_vars(class);
+        for subroutine_dec in &class.subroutine_decs {
+            self.symbol_table.enter_subroutine(&class.name.name, subroutine_dec);
+            self.subroutine_kind = subroutine_dec.kind;
+            self.check_statements(&subroutine_dec.statements);
+        }
+
+        if self.errors.is_empty() {
+            return Ok(())
+        }
+        let mut errors = mem::take(&mut self.errors);
+        errors.sort_by_key(|e| e.span().map(|s| s.byte_range.start));
+        Err(errors)
+    }
+
+    fn check_statements(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            match statement {
+                Statement::Let { index, value, .. } => {
+                    if let Some(index) = index {
+                        self.check_expression(index);
+                    }
+                    self.check_expression(value);
+                },
+                Statement::If { condition, then_statements, else_statements, .. } => {
+                    self.check_expression(condition);
+                    self.check_statements(then_statements);
+                    if let Some(else_statements) = else_statements {
+                        self.check_statements(else_statements);
+                    }
+                },
+                Statement::While { condition, statements, .. } => {
+                    self.check_expression(condition);
+                    self.check_statements(statements);
+                },
+                Statement::Do { call, .. } => self.check_call(call),
+                Statement::Return { value, .. } => {
+                    if let Some(value) = value {
+                        self.check_expression(value);
+                    }
+                },
+            }
+        }
+    }
+
+    fn check_expression(&mut self, expression: &Expression) {
+        self.check_term(&expression.term);
+        for (_, term) in &expression.rest {
+            self.check_term(term);
+        }
+    }
+
+    fn check_term(&mut self, term: &Term) {
+        match term {
+            Term::ArrayAccess { index, .. } => self.check_expression(index),
+            Term::SubroutineCall(call) => self.check_call(call),
+            Term::Parenthesized { expression, .. } => self.check_expression(expression),
+            Term::Unary { term, .. } => self.check_term(term),
+            _ => (),
+        }
+    }
+
+    fn check_call(&mut self, call: &SubroutineCall) {
+        for argument in &call.arguments {
+            self.check_expression(argument);
+        }
+
+        // 呼び出すクラスと、メソッドとして呼び出しているかを決める。
+        // 変数のメソッドか自身のメソッドの呼び出しでなければクラス名での呼び出し
+        let (class_name, as_method) = match &call.receiver {
+            Some(r) => match self.symbol_table.type_of(&r.name) {
+                Some(ty) => (ty.to_string(), true),
+                None => (r.name.clone(), false),
+            },
+            None => (self.class.name.name.clone(), true),
+        };
+        if !self.registry.contains_class(&class_name) {
+            return
+        }
+
+        let name = format!("{}.{}", class_name, call.name.name);
+        let signature = match self.registry.get(&class_name, &call.name.name) {
+            Some(signature) => signature,
+            None => {
+                self.errors.push(CompileError::UnknownSubroutine {
+                    name,
+                    suggestion: self.registry.similar(&class_name, &call.name.name)
+                        .map(|s| s.to_string()),
+                    span: call.name.span.clone(),
+                });
+                return
+            }
+        };
+
+        // 関数にはthisがないので、自身のメソッドを呼び出せない
+        let without_this = call.receiver.is_none() &&
+                           self.subroutine_kind == SubroutineKind::Function;
+        if as_method != (signature.kind == SubroutineKind::Method) ||
+           (without_this && signature.kind == SubroutineKind::Method) {
+            self.errors.push(CompileError::KindMismatch {
+                name,
+                declared: signature.kind,
+                span: call.span.clone(),
+            });
+        } else if signature.parameters.len() != call.arguments.len() {
+            self.errors.push(CompileError::ArgumentCount {
+                name,
+                expected: signature.parameters.len(),
+                found: call.arguments.len(),
+                span: call.span.clone(),
+            });
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{Registry, CallChecker, Signature, distance};
+    use crate::ast::*;
+    use crate::compilation_engine::parse_str;
+
+    #[test]
+    fn test_registry() {
+        let registry = Registry::from_class(&parse_str("class P {
+            constructor P new(int x, int y) { return this; }
+            method void draw() { return; }
+        }").unwrap());
+        assert!(registry.contains_class("P"));
+        assert_eq!(registry.get("P", "new"), Some(&Signature {
+            kind: SubroutineKind::Constructor,
+            return_type: Some(Type::Class("P".to_string())),
+            parameters: vec![Type::Int, Type::Int],
+        }));
+        assert_eq!(registry.get("P", "draw").unwrap().return_type, None);
+        assert_eq!(registry.get("P", "move"), None);
+        assert_eq!(registry.get("Q", "new"), None);
+        assert_eq!(registry.names("P"), vec!["draw", "new"]);
+        assert!(registry.names("Q").is_empty());
+    }
+
+    #[test]
+    fn test_registry_with_os() {
+        let mut registry = Registry::with_os();
+        for class in ["Math", "String", "Array", "Output", "Screen", "Keyboard", "Memory", "Sys"] {
+            assert!(registry.contains_class(class), "{}", class);
+        }
+        assert_eq!(registry.get("Output", "printInt"), Some(&Signature {
+            kind: SubroutineKind::Function,
+            return_type: None,
+            parameters: vec![Type::Int],
+        }));
+        assert_eq!(registry.get("String", "appendChar").unwrap().return_type,
+                   Some(Type::Class("String".to_string())));
+
+        // 同じ名前のクラスはユーザーのもので置き換える
+        registry.add_class(&parse_str("class Math { function int twice(int x) { return x; } }").unwrap());
+        assert_eq!(registry.names("Math"), vec!["twice"]);
+    }
+
+    #[test]
+    fn test_similar() {
+        assert_eq!(distance("printInt", "printint"), 1);
+        assert_eq!(distance("abc", ""), 3);
+        assert_eq!(distance("kitten", "sitting"), 3);
+
+        let registry = Registry::with_os();
+        assert_eq!(registry.similar("Output", "printint"), Some("printInt"));
+        assert_eq!(registry.similar("Output", "PRINTLN"), Some("println"));
+        assert_eq!(registry.similar("Output", "printStrin"), Some("printString"));
+        assert_eq!(registry.similar("Math", "sqr"), Some("sqrt"));
+        assert_eq!(registry.similar("Math", "power"), None);
+        assert_eq!(registry.similar("Game", "run"), None);
+    }
+
+    #[test]
+    fn test_check_calls() {
+        let mut registry = Registry::new();
+        registry.add_class(&parse_str("class P {
+            constructor P new(int x, int y) { return this; }
+            method void draw() { return; }
+            function int count() { return 0; }
+        }").unwrap());
+        let main = parse_str("class Main {
+            function void main() {
+                var P p;
+                let p = P.new(1, 2);
+                do p.draw();
+                do Output.printInt(P.count());
+                do P.new(1);
+                do P.draw();
+                do p.count();
+                do P.move(p.draw(1));
+                do run();
+                return;
+            }
+            method void run() { do main(); do run(); return; }
+            constructor Main new() { do run(); return this; }
+        }").unwrap();
+        registry.add_class(&main);
+
+        let errors: Vec<(String, usize)> = CallChecker::new(&registry, &main).check_class()
+            .unwrap_err().iter()
+            .map(|e| (e.message(), e.span().unwrap().start_line))
+            .collect();
+        assert_eq!(errors, vec![
+            ("'P.new' takes 2 arguments but 1 were given".to_string(), 7),
+            ("'P.draw' is a method and must be called on an object".to_string(), 8),
+            ("'P.count' is a function, not a method".to_string(), 9),
+            ("unknown subroutine: 'P.move'".to_string(), 10),
+            ("'P.draw' takes 0 arguments but 1 were given".to_string(), 10),
+            ("'Main.run' is a method and must be called on an object".to_string(), 11),
+            ("'Main.main' is a function, not a method".to_string(), 14),
+        ]);
+    }
+
+    #[test]
+    fn test_check_os_calls() {
+        let main = parse_str("class Main {
+            function void main() {
+                var String s;
+                let s = String.new(8);
+                do s.appendChar(String.newLine());
+                do Output.printint(Math.max(1, 2));
+                do Screen.drawLine(0, 0, 1);
+                do Memory.deAlloc(Array.new(2));
+                do Sys.wait(Keyboard.readInt(s), 1);
+                return;
+            }
+        }").unwrap();
+        let mut registry = Registry::with_os();
+        registry.add_class(&main);
+
+        let errors = CallChecker::new(&registry, &main).check_class().unwrap_err();
+        let messages: Vec<(String, usize)> = errors.iter()
+            .map(|e| (e.message(), e.span().unwrap().start_line))
+            .collect();
+        assert_eq!(messages, vec![
+            ("unknown subroutine: 'Output.printint'".to_string(), 6),
+            ("'Screen.drawLine' takes 4 arguments but 3 were given".to_string(), 7),
+            ("'Sys.wait' takes 1 arguments but 2 were given".to_string(), 9),
+        ]);
+        assert_eq!(errors[0].suggestion(), Some("printInt"));
+        assert_eq!(errors[1].suggestion(), None);
+    }
+}
@@ -0,0 +1,76 @@
+//! Spanはソースコード上の範囲を表す。
+//! 構文木の各ノードやエラーの位置を示すのに使う
+
+use std::fmt;
+use std::ops::Range;
+use std::rc::Rc;
+
+
+/// ソースコード上の範囲。行番号と列番号は1から始まる。
+/// 列番号は行の先頭からの文字数で数え、end_colは範囲の最後の文字の次の列
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Span {
+    /// ファイル名。標準入力などファイル名がないときはNone
+    pub file: Option<Rc<str>>,
+    pub start_line: usize,
+    pub start_col: usize,
+    pub end_line: usize,
+    pub end_col: usize,
+    /// ファイルの先頭からのバイト位置
+    pub byte_range: Range<usize>,
+}
+
+impl Span {
+    /// selfの始まりからotherの終わりまでの範囲を返す
+    pub fn to(&self, other: &Span) -> Span {
+        Span {
+            file: self.file.clone(),
+            start_line: self.start_line,
+            start_col: self.start_col,
+            end_line: other.end_line,
+            end_col: other.end_col,
+            byte_range: self.byte_range.start..other.byte_range.end,
+        }
+    }
+}
+
+/// "ファイル名:行番号:列番号"の形式で表示する
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+        }
+        write!(f, "{}:{}", self.start_line, self.start_col)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::Span;
+
+    #[test]
+    fn test_span_to() {
+        let a = Span {
+            start_line: 2, start_col: 5, end_line: 2, end_col: 8, byte_range: 10..13,
+            ..Span::default()
+        };
+        let b = Span {
+            start_line: 3, start_col: 1, end_line: 5, end_col: 2, byte_range: 20..40,
+            ..Span::default()
+        };
+        let c = a.to(&b);
+        assert_eq!((c.start_line, c.start_col, c.end_line, c.end_col), (2, 5, 5, 2));
+        assert_eq!(c.byte_range, 10..40);
+        assert_eq!(b.to(&b), b);
+    }
+
+    #[test]
+    fn test_span_display() {
+        let mut s = Span { start_line: 3, start_col: 7, ..Span::default() };
+        assert_eq!(s.to_string(), "3:7");
+
+        s.file = Some("Main.jack".into());
+        assert_eq!(s.to_string(), "Main.jack:3:7");
+    }
+}
@@ -2,6 +2,12 @@
 // 仕様は267page
 
 use std::collections::HashMap;
+use std::mem;
+
+use super::span::Span;
+use super::ast::{Class, ClassVarKind, SubroutineDec, SubroutineKind};
+use super::error::CompileError;
+use super::vm_writer::Segment;
 
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -12,13 +18,66 @@ pub enum Kind {
     Var, // 関数内の変数
 }
 
+impl Kind {
+    // 属性に対応するvmのセグメントを返す
+    pub fn segment(self) -> Segment {
+        match self {
+            Kind::Static => Segment::Static,
+            Kind::Field => Segment::This,
+            Kind::Arg => Segment::Argument,
+            Kind::Var => Segment::Local,
+        }
+    }
+
+    // 属性が属するスコープを返す
+    pub fn scope(self) -> Scope {
+        match self {
+            Kind::Static | Kind::Field => Scope::Class,
+            Kind::Arg | Kind::Var => Scope::Subroutine,
+        }
+    }
+}
+
 const KIND_LIST: [Kind;4] = [Kind::Static, Kind::Field, Kind::Arg, Kind::Var];
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Class, // クラスのスコープ
+    Subroutine, // サブルーチンのスコープ
+}
+
+// 識別子の情報
+#[derive(Debug, Clone, PartialEq)]
+pub struct SymbolInfo {
+    pub ty: String,
+    pub kind: Kind,
+    pub index: usize,
+    pub scope: Scope,
+    // 宣言した位置
+    pub declared_at: Span,
+}
+
+impl SymbolInfo {
+    // 識別子を読み書きするvmのセグメントを返す
+    pub fn segment(&self) -> Segment {
+        self.kind.segment()
+    }
+}
+
 pub struct SymbolTable {
-    // HashMap<名前, (型, 属性, インデックス)>
-    class_scope: HashMap<String, (String, Kind, usize)>,
-    subroutine_scope: HashMap<String, (String, Kind, usize)>,
-    counter: HashMap<Kind, usize>
+    // HashMap<名前, 識別子の情報>
+    class_scope: HashMap<String, SymbolInfo>,
+    subroutine_scope: HashMap<String, SymbolInfo>,
+    counter: HashMap<Kind, usize>,
+    // サブルーチンの変数がクラスの変数を隠すときに警告するか
+    warn_shadowing: bool,
+    warnings: Vec<CompileError>,
+}
+
+impl Default for SymbolTable {
+    fn default() -> SymbolTable {
+        SymbolTable::new()
+    }
 }
 
 impl SymbolTable {
@@ -31,10 +90,22 @@ impl SymbolTable {
         SymbolTable {
             class_scope: HashMap::new(),
             subroutine_scope: HashMap::new(),
-            counter
+            counter,
+            warn_shadowing: false,
+            warnings: Vec::new(),
         }
     }
 
+    // trueにするとサブルーチンの変数や引数がクラスの変数を隠すときに警告する
+    pub fn set_warn_shadowing(&mut self, warn_shadowing: bool) {
+        self.warn_shadowing = warn_shadowing;
+    }
+
+    // これまでの警告を取り出す
+    pub fn take_warnings(&mut self) -> Vec<CompileError> {
+        mem::take(&mut self.warnings)
+    }
+
     // subroutine_scopeのテーブルを初期化する
     pub fn start_subroutine(&mut self) {
         self.subroutine_scope = HashMap::new();
@@ -43,19 +114,85 @@ impl SymbolTable {
         *self.counter.get_mut(&Kind::Var).unwrap() = 0;
     }
 
-    // symbol tableに値を追加する
-    pub fn define(&mut self, name: String, ty: String, kind: Kind) {
-        let index = *self.counter.get(&kind).unwrap();
-        match kind {
-            Kind::Static | Kind::Field => {
-                self.class_scope.insert(name, (ty, kind, index));
-            },
-            Kind::Arg | Kind::Var => {
-                self.subroutine_scope.insert(name, (ty, kind, index));
+    // symbol tableに値を追加する。declared_atは宣言した位置。
+    // 同じスコープで宣言済みの名前のときは何も追加せずにエラーを返す
+    pub fn define(&mut self, name: String, ty: String, kind: Kind, declared_at: Span)
+        -> Result<(), CompileError> {
+        let scope = kind.scope();
+        let table = match scope {
+            Scope::Class => &self.class_scope,
+            Scope::Subroutine => &self.subroutine_scope,
+        };
+        if let Some(previous) = table.get(&name) {
+            return Err(CompileError::Redeclared {
+                name,
+                span: declared_at,
+                previous: Box::new(previous.declared_at.clone()),
+            })
+        }
+
+        if self.warn_shadowing && scope == Scope::Subroutine {
+            if let Some(field) = self.class_scope.get(&name) {
+                self.warnings.push(CompileError::Shadowed {
+                    name: name.clone(),
+                    span: declared_at.clone(),
+                    previous: Box::new(field.declared_at.clone()),
+                });
             }
         }
 
+        let index = *self.counter.get(&kind).unwrap();
+        let info = SymbolInfo { ty, kind, index, scope, declared_at };
+        match scope {
+            Scope::Class => self.class_scope.insert(name, info),
+            Scope::Subroutine => self.subroutine_scope.insert(name, info),
+        };
+
         *self.counter.get_mut(&kind).unwrap() += 1;
+        Ok(())
+    }
+
+    // クラスの変数を全て宣言する。再宣言があっても残りの宣言を続け、
+    // 再宣言のエラーをソースコードの順に返す
+    pub fn define_class_vars(&mut self, class: &Class) -> Vec<CompileError> {
+        let mut errors = Vec::new();
+        for class_var_dec in &class.class_var_decs {
+            let kind = match class_var_dec.kind {
+                ClassVarKind::Static => Kind::Static,
+                ClassVarKind::Field => Kind::Field,
+            };
+            for name in &class_var_dec.names {
+                let ty = class_var_dec.ty.name().to_string();
+                if let Err(e) = self.define(name.name.clone(), ty, kind, name.span.clone()) {
+                    errors.push(e);
+                }
+            }
+        }
+        errors
+    }
+
+    // サブルーチンのスコープを始め、引数とローカル変数を宣言する。メソッドの
+    // ときは最初の引数をthisとし、宣言した位置はメソッドの名前にする。
+    // 再宣言のエラーはdefine_class_varsと同じように返す
+    pub fn enter_subroutine(&mut self, class_name: &str, subroutine_dec: &SubroutineDec)
+        -> Vec<CompileError> {
+        self.start_subroutine();
+        let mut errors = Vec::new();
+        if subroutine_dec.kind == SubroutineKind::Method {
+            let _ = self.define("this".to_string(), class_name.to_string(), Kind::Arg,
+                                subroutine_dec.name.span.clone());
+        }
+
+        let parameters = subroutine_dec.parameters.iter().map(|p| (&p.name, &p.ty, Kind::Arg));
+        let vars = subroutine_dec.var_decs.iter()
+            .flat_map(|v| v.names.iter().map(move |n| (n, &v.ty, Kind::Var)));
+        for (name, ty, kind) in parameters.chain(vars) {
+            let ty = ty.name().to_string();
+            if let Err(e) = self.define(name.name.clone(), ty, kind, name.span.clone()) {
+                errors.push(e);
+            }
+        }
+        errors
     }
 
     // 引数で与えられた属性について、それが現在のスコープで定義されている数を返す
@@ -63,44 +200,26 @@ impl SymbolTable {
         *self.counter.get(&kind).unwrap()
     }
 
+    // 引数で与えられた名前の識別子を現在のスコープで探し、その情報を返す。
+    // サブルーチンのスコープを先に探す。見つからないときはNoneを返す
+    pub fn lookup(&self, name: &str) -> Option<&SymbolInfo> {
+        self.subroutine_scope.get(name).or_else(|| self.class_scope.get(name))
+    }
+
     // 引数で与えられた名前の識別子を現在のスコープで探し、その属性を返す。
     // 見つからないときはNoneを返す
-    fn kind_of(&self, name: &str) -> Option<Kind> {
-        if let Some((_, k, _)) = self.subroutine_scope.get(name) {
-            return Some(*k)
-        }
-
-        if let Some((_, k, _)) = self.class_scope.get(name) {
-            return Some(*k)
-        }
-
-        None
+    pub fn kind_of(&self, name: &str) -> Option<Kind> {
+        self.lookup(name).map(|info| info.kind)
     }
 
     // 引数で与えられた名前の識別子を現在のスコープで探し、その型を返す
     pub fn type_of(&self, name: &str) -> Option<&str> {
-        if let Some((t, _, _)) = self.subroutine_scope.get(name) {
-            return Some(t)
-        }
-
-        if let Some((t, _, _)) = self.class_scope.get(name) {
-            return Some(t)
-        }
-
-        None
+        self.lookup(name).map(|info| info.ty.as_str())
     }
 
     // 引数で与えられた名前の識別子を現在のスコープで探し、そのインデックスを返す
     pub fn index_of(&self, name: &str) -> Option<usize> {
-        if let Some((_, _, i)) = self.subroutine_scope.get(name) {
-            return Some(*i)
-        }
-
-        if let Some((_, _, i)) = self.class_scope.get(name) {
-            return Some(*i)
-        }
-
-        None
+        self.lookup(name).map(|info| info.index)
     }
 }
 
@@ -109,17 +228,22 @@ impl SymbolTable {
 mod test_symbol_table {
     use super::SymbolTable;
     use super::Kind;
+    use super::Scope;
+    use crate::error::CompileError;
+    use crate::span::Span;
+    use crate::vm_writer::Segment;
+    use crate::compilation_engine::parse_str;
 
     #[test]
     fn test_new() {
-        let mut st = SymbolTable::new();
+        let _st = SymbolTable::new();
     }
 
     #[test]
     fn test_define() {
         let mut st = SymbolTable::new();
-        st.define("name".to_string(), "type".to_string(), Kind::Var);
-        st.define("namae".to_string(), "kata".to_string(), Kind::Field);
+        st.define("name".to_string(), "type".to_string(), Kind::Var, Span::default()).unwrap();
+        st.define("namae".to_string(), "kata".to_string(), Kind::Field, Span::default()).unwrap();
     }
 
     #[test]
@@ -127,13 +251,13 @@ mod test_symbol_table {
         let mut st = SymbolTable::new();
         assert_eq!(st.var_count(Kind::Static), 0);
         
-        st.define("name".to_string(), "type".to_string(), Kind::Static);
+        st.define("name".to_string(), "type".to_string(), Kind::Static, Span::default()).unwrap();
         assert_eq!(st.var_count(Kind::Static), 1);
 
-        st.define("namae".to_string(), "kata".to_string(), Kind::Static);
+        st.define("namae".to_string(), "kata".to_string(), Kind::Static, Span::default()).unwrap();
         assert_eq!(st.var_count(Kind::Static), 2);
 
-        st.define("nombre".to_string(), "tipos".to_string(), Kind::Var);
+        st.define("nombre".to_string(), "tipos".to_string(), Kind::Var, Span::default()).unwrap();
         assert_eq!(st.var_count(Kind::Var), 1);
     }
 
@@ -142,10 +266,10 @@ mod test_symbol_table {
         let mut st = SymbolTable::new();
         assert_eq!(st.type_of("name"), None);
 
-        st.define("name".to_string(), "type".to_string(), Kind::Static);
+        st.define("name".to_string(), "type".to_string(), Kind::Static, Span::default()).unwrap();
         assert_eq!(st.type_of("name"), Some("type"));
 
-        st.define("namae".to_string(), "kata".to_string(), Kind::Arg);
+        st.define("namae".to_string(), "kata".to_string(), Kind::Arg, Span::default()).unwrap();
         assert_eq!(st.type_of("name"), Some("type"));
     }
 
@@ -154,10 +278,10 @@ mod test_symbol_table {
         let mut st = SymbolTable::new();
         assert_eq!(st.kind_of("name"), None);
 
-        st.define("name".to_string(), "type".to_string(), Kind::Static);
+        st.define("name".to_string(), "type".to_string(), Kind::Static, Span::default()).unwrap();
         assert_eq!(st.kind_of("name"), Some(Kind::Static));
 
-        st.define("namae".to_string(), "kata".to_string(), Kind::Arg);
+        st.define("namae".to_string(), "kata".to_string(), Kind::Arg, Span::default()).unwrap();
         assert_eq!(st.kind_of("namae"), Some(Kind::Arg));
     }
 
@@ -166,10 +290,10 @@ mod test_symbol_table {
         let mut st = SymbolTable::new();
         assert_eq!(st.index_of("name"), None);
 
-        st.define("name".to_string(), "type".to_string(), Kind::Static);
+        st.define("name".to_string(), "type".to_string(), Kind::Static, Span::default()).unwrap();
         assert_eq!(st.index_of("name"), Some(0));
 
-        st.define("namae".to_string(), "kata".to_string(), Kind::Static);
+        st.define("namae".to_string(), "kata".to_string(), Kind::Static, Span::default()).unwrap();
         assert_eq!(st.index_of("namae"), Some(1));
     }
 
@@ -177,13 +301,13 @@ mod test_symbol_table {
     fn test_start_subroutine() {
         let mut st = SymbolTable::new();
 
-        st.define("name".to_string(), "type".to_string(), Kind::Static);
+        st.define("name".to_string(), "type".to_string(), Kind::Static, Span::default()).unwrap();
         assert_eq!(st.index_of("name"), Some(0));
 
         st.start_subroutine();
         assert_eq!(st.index_of("name"), Some(0));
 
-        st.define("namae".to_string(), "kata".to_string(), Kind::Var);
+        st.define("namae".to_string(), "kata".to_string(), Kind::Var, Span::default()).unwrap();
         assert_eq!(st.index_of("namae"), Some(0));
         assert_eq!(st.kind_of("namae"), Some(Kind::Var));
 
@@ -191,4 +315,100 @@ mod test_symbol_table {
         assert_eq!(st.index_of("namae"), None);
         assert_eq!(st.kind_of("namae"), None);
     }
+
+    #[test]
+    fn test_lookup() {
+        let mut st = SymbolTable::new();
+        assert!(st.lookup("x").is_none());
+
+        let span = Span { start_line: 2, start_col: 9, ..Span::default() };
+        st.define("x".to_string(), "int".to_string(), Kind::Field, span.clone()).unwrap();
+        st.define("y".to_string(), "int".to_string(), Kind::Field, Span::default()).unwrap();
+        let info = st.lookup("y").unwrap();
+        assert_eq!((info.kind, info.index, info.scope), (Kind::Field, 1, Scope::Class));
+        assert_eq!(info.segment(), Segment::This);
+
+        // サブルーチンのスコープの識別子を優先する
+        st.start_subroutine();
+        st.define("x".to_string(), "Array".to_string(), Kind::Var, Span::default()).unwrap();
+        let info = st.lookup("x").unwrap();
+        assert_eq!((info.ty.as_str(), info.scope), ("Array", Scope::Subroutine));
+        assert_eq!(info.segment(), Segment::Local);
+
+        st.start_subroutine();
+        assert_eq!(st.lookup("x").unwrap().declared_at, span);
+    }
+
+    #[test]
+    fn test_kind_segment() {
+        assert_eq!(Kind::Static.segment(), Segment::Static);
+        assert_eq!(Kind::Field.segment(), Segment::This);
+        assert_eq!(Kind::Arg.segment(), Segment::Argument);
+        assert_eq!(Kind::Var.segment(), Segment::Local);
+    }
+
+    #[test]
+    fn test_define_redeclared() {
+        let span = |line| Span { start_line: line, start_col: 1, ..Span::default() };
+        let mut st = SymbolTable::new();
+        st.define("x".to_string(), "int".to_string(), Kind::Field, span(1)).unwrap();
+        // 同じスコープでの再宣言はエラーにして、インデックスを飛ばさない
+        assert_eq!(st.define("x".to_string(), "int".to_string(), Kind::Static, span(2)),
+                   Err(CompileError::Redeclared {
+                       name: "x".to_string(), span: span(2), previous: Box::new(span(1))
+                   }));
+        assert_eq!(st.var_count(Kind::Static), 0);
+
+        st.start_subroutine();
+        st.define("a".to_string(), "int".to_string(), Kind::Arg, span(3)).unwrap();
+        assert!(st.define("a".to_string(), "int".to_string(), Kind::Var, span(4)).is_err());
+        st.define("b".to_string(), "int".to_string(), Kind::Var, span(5)).unwrap();
+        assert_eq!(st.index_of("b"), Some(0));
+
+        // 警告しない設定ではクラスの変数を隠してもよい
+        st.define("x".to_string(), "char".to_string(), Kind::Var, span(6)).unwrap();
+        assert_eq!(st.type_of("x"), Some("char"));
+        assert!(st.take_warnings().is_empty());
+    }
+
+    #[test]
+    fn test_define_shadowed() {
+        let span = |line| Span { start_line: line, start_col: 1, ..Span::default() };
+        let mut st = SymbolTable::new();
+        st.set_warn_shadowing(true);
+        st.define("x".to_string(), "int".to_string(), Kind::Field, span(1)).unwrap();
+        st.start_subroutine();
+        st.define("x".to_string(), "int".to_string(), Kind::Arg, span(2)).unwrap();
+        st.define("y".to_string(), "int".to_string(), Kind::Var, span(3)).unwrap();
+        assert_eq!(st.take_warnings(), vec![CompileError::Shadowed {
+            name: "x".to_string(), span: span(2), previous: Box::new(span(1))
+        }]);
+        assert_eq!(st.take_warnings(), Vec::new());
+    }
+
+    #[test]
+    fn test_define_class() {
+        let class = parse_str("class A {
+            static int n, n;
+            field Array a;
+            method void f(int x, char c) { var int x, y; return; }
+            function void g(boolean b) { return; }
+        }").unwrap();
+        let mut st = SymbolTable::new();
+        let errors = st.define_class_vars(&class);
+        assert_eq!(errors.len(), 1);
+        assert_eq!((st.var_count(Kind::Static), st.var_count(Kind::Field)), (1, 1));
+
+        // メソッドは最初の引数がthis
+        let errors = st.enter_subroutine("A", &class.subroutine_decs[0]);
+        assert!(matches!(errors.as_slice(), [CompileError::Redeclared { name, .. }] if name == "x"));
+        assert_eq!(st.type_of("this"), Some("A"));
+        assert_eq!(st.index_of("c"), Some(2));
+        assert_eq!((st.var_count(Kind::Arg), st.var_count(Kind::Var)), (3, 1));
+
+        assert!(st.enter_subroutine("A", &class.subroutine_decs[1]).is_empty());
+        assert_eq!(st.kind_of("this"), None);
+        assert_eq!(st.index_of("b"), Some(0));
+        assert_eq!(st.kind_of("a"), Some(Kind::Field));
+    }
 }
@@ -1,36 +1,158 @@
 //! Tokenizerは入力からTokenを取り出す。
 //! TokenはToken構造体。
-//! Tokenizerはiteratorをimplementしているため、for文で使える
+//! Tokenizerはiteratorをimplementしているため、for文で使える。
+//! 読み戻しはせずに先読みで処理するので、標準入力などシークできない入力も
+//! 読める
 
+use std::collections::VecDeque;
+use std::io;
 use std::io::Read;
 use std::io::BufReader;
-use std::io::SeekFrom;
-use std::io::Seek;
 use std::iter::Iterator;
+use std::mem;
+use std::rc::Rc;
 
 pub mod token;
 use token::Token;
-
+use token::MAX_INTEGER;
+use super::span::Span;
+use super::error::CompileError;
+
+
+// ストリーム上の位置。lineとcolは1から、offsetは0から始まる。
+// colは文字数で、offsetはバイト数で数える
+#[derive(Debug, Clone, Copy)]
+struct Position {
+    line: usize,
+    col: usize,
+    offset: usize,
+}
 
 pub struct Tokenizer<R> {
-    stream: BufReader<R>,
+    stream: PeekReader<R>,
+    file: Option<Rc<str>>,
     line_number: usize,
+    // 現在の行の先頭までに読んだ文字数
+    line_start: usize,
+    // 読んでいるトークンの始まりの位置
+    token_start: Position,
     token: Option<Token>,
+    // 現在のトークンの範囲
+    span: Span,
+    // 字句解析のエラー。エラーの後も続きのトークンを読む
+    errors: Vec<CompileError>,
+    // trueのときはトークンの始まりにならない文字をエラーにせず読み飛ばす
+    lenient: bool,
+    // 読み飛ばした文字などの警告
+    warnings: Vec<CompileError>,
 }
 
-impl<R: Read + Seek> Tokenizer<R> {
+impl<R: Read> Tokenizer<R> {
     pub fn new(stream: R) -> Tokenizer<R> {
         Tokenizer {
-            stream: BufReader::new(stream),
+            stream: PeekReader::new(stream),
+            file: None,
             line_number: 1, // 行番号は1から始める
+            line_start: 0,
+            token_start: Position { line: 1, col: 1, offset: 0 },
             token: None,
+            span: Span::default(),
+            errors: Vec::new(),
+            lenient: false,
+            warnings: Vec::new(),
         }
     }
 
+    /// エラーの位置に使うファイル名を指定してTokenizerを作る
+    pub fn with_file(stream: R, file: &str) -> Tokenizer<R> {
+        let mut t = Tokenizer::new(stream);
+        t.file = Some(file.into());
+        t
+    }
+
+    /// trueにすると、トークンの始まりにならない文字をエラーにせず
+    /// 警告にして読み飛ばす。以前の動作との互換のために使う
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
+    }
+
+    /// 警告を取り出す
+    pub fn take_warnings(&mut self) -> Vec<CompileError> {
+        mem::take(&mut self.warnings)
+    }
+
+    /// 読み込み位置の行番号を返す。トークンの位置はcurrent_spanを使う
     pub fn get_line_number(&self) -> usize {
         self.line_number
     }
 
+    pub fn get_file(&self) -> Option<Rc<str>> {
+        self.file.clone()
+    }
+
+    /// 現在のトークンの範囲を返す。
+    /// 最後まで読み終えたときはファイルの終わりの位置を返す
+    pub fn current_span(&self) -> &Span {
+        &self.span
+    }
+
+    /// これまでに見つけた字句解析のエラーをソースコードの順に取り出す
+    pub fn take_errors(&mut self) -> Vec<CompileError> {
+        mem::take(&mut self.errors)
+    }
+
+    // 読み込み位置を返す
+    fn position(&mut self) -> Position {
+        let offset = self.stream.offset;
+        Position {
+            line: self.get_line_number(),
+            col: self.stream.chars - self.line_start + 1,
+            offset,
+        }
+    }
+
+    // 改行を読んだときに呼び、行番号と行の先頭の位置を更新する
+    fn newline(&mut self) {
+        self.line_number += 1;
+        self.line_start = self.stream.chars;
+    }
+
+    // startから読み込み位置までの範囲を返す
+    fn span_from(&mut self, start: Position) -> Span {
+        let end = self.position();
+        Span {
+            file: self.get_file(),
+            start_line: start.line,
+            start_col: start.col,
+            end_line: end.line,
+            end_col: end.col,
+            byte_range: start.offset..end.offset,
+        }
+    }
+
+    // UTF-8として正しくないバイトを読んだときに呼び、そのバイトを指す
+    // エラーを記録する。そのバイトは読み飛ばす
+    fn invalid_utf8(&mut self) {
+        let end = self.position();
+        let start = Position { col: end.col - 1, offset: end.offset - 1, ..end };
+        let span = self.span_from(start);
+        self.errors.push(CompileError::InvalidUtf8 { span });
+    }
+
+    // 数字の文字列を整数のトークンにする。32768は-32768の一部としてだけ
+    // 使えるので構文解析で確かめ、それより大きい場合はここでエラーにする。
+    // エラーのときも構文解析を続けられるように0のトークンを返す
+    fn integer_token(&mut self, t: String) -> Token {
+        match Token::new(t.clone()) {
+            Some(Token::Integer(i)) if i <= MAX_INTEGER + 1 => Token::Integer(i),
+            _ => {
+                let span = self.span_from(self.token_start);
+                self.errors.push(CompileError::IntegerOverflow { value: t, span });
+                Token::Integer(0)
+            }
+        }
+    }
+
     /// 現在のトークンへの参照を返す
     pub fn get_current_token(&self) -> Option<&Token> {
         self.token.as_ref()
@@ -44,11 +166,35 @@ impl<R: Read + Seek> Tokenizer<R> {
     }
 }
 
-impl<R: Read + Seek> Iterator for Tokenizer<R> {
+impl<R: Read> Iterator for Tokenizer<R> {
     type Item = Token;
 
+    /// トークンを1つ読み、その範囲をcurrent_spanで返せるようにする
     fn next(&mut self) -> Option<Token> {
+        let token = self.read_token();
+        // 入力を読み出せなかったときは、そこで途切れたことをエラーにする
+        if let Some(e) = self.stream.error.take() {
+            let name = self.file.as_deref().unwrap_or("<input>").to_string();
+            self.errors.push(CompileError::io(&name, &e));
+        }
+        self.span = match token {
+            Some(_) => self.span_from(self.token_start),
+            // 終端では幅0の範囲にする
+            None => {
+                let end = self.position();
+                self.span_from(end)
+            }
+        };
+        token
+    }
+}
+
+impl<R: Read> Tokenizer<R> {
+    // 空白とコメントを飛ばしてトークンを1つ読む。
+    // トークンの始まりの位置はtoken_startに残す
+    fn read_token(&mut self) -> Option<Token> {
         loop {
+            self.token_start = self.position();
             match self.stream.matches(&["/*", "//"]) {
                 // ブロックコメントの場合
                 Matches::Str("/*") => {
@@ -57,11 +203,18 @@ impl<R: Read + Seek> Iterator for Tokenizer<R> {
                         match self.stream.matches(&["*/"]) {
                             // コメントが終わったらloopを抜ける
                             Matches::Str(_) => break,
+                            // コメントの中の改行も数える
+                            Matches::Char('\n') => self.newline(),
                             // 関係の無い文字は飛ばす
-                            Matches::Char(_) => (), 
-                            // ブロックコメントのまま終端まで読んだらNoneを
-                            // 返す
-                            Matches::None => return None
+                            Matches::Char(_) => (),
+                            Matches::Invalid(_) => self.invalid_utf8(),
+                            // ブロックコメントのまま終端まで読んだらエラー
+                            // にしてNoneを返す
+                            Matches::None => {
+                                let span = self.span_from(self.token_start);
+                                self.errors.push(CompileError::UnterminatedComment { span });
+                                return None
+                            }
                         }
                     }
                 },
@@ -70,10 +223,14 @@ impl<R: Read + Seek> Iterator for Tokenizer<R> {
                     // 次の\nまで飛ばす
                     loop {
                         match self.stream.matches(&["\r\n", "\n"]) {
-                            // \r\nまたは\nが見つかったらloopを抜ける
-                            Matches::Str(_) => break,
+                            // \r\nまたは\nが見つかったら行を進めてloopを抜ける
+                            Matches::Str(_) => {
+                                self.newline();
+                                break
+                            },
                             // 関係の無い文字は飛ばす
                             Matches::Char(_) => (),
+                            Matches::Invalid(_) => self.invalid_utf8(),
                             // 行コメントのまま終端まで読んだらNoneを返す
                             Matches::None => return None
                         }
@@ -82,15 +239,24 @@ impl<R: Read + Seek> Iterator for Tokenizer<R> {
                 Matches::Str(_) => panic!(),
                 Matches::Char(c) => match c {
                     // 改行の場合
-                    '\n' => {
-                        // 行数をインクリメントする
-                        self.line_number += 1;
-                    }
+                    '\n' => self.newline(),
                     '"' => {
                         // 次のダブルクオートまでの文字をトークンとして排出する
                         let mut t = String::new();
 
-                        while let Some(c) = self.stream.read_char() {
+                        // 文字列の中に改行は書けないので、改行の手前で
+                        // 止めてエラーにする
+                        while let Some(c) = self.stream.peek_char() {
+                            if let Ok('\r' | '\n') = c {
+                                break
+                            }
+                            let c = match self.stream.read_char().unwrap() {
+                                Ok(c) => c,
+                                Err(_) => {
+                                    self.invalid_utf8();
+                                    continue
+                                }
+                            };
                             // "が見つかったらそれまでの文字列をトークン
                             // として返す
                             if c == '"'{
@@ -99,8 +265,11 @@ impl<R: Read + Seek> Iterator for Tokenizer<R> {
                             // 文字はすべて追加する
                             t.push(c);
                         }
-                        // 終端まで読んでしまった場合はNoneを返す
-                        return None
+                        // 改行か終端まで読んでしまった場合は文字列の始まり
+                        // からのエラーにし、そこまでを文字列として返す
+                        let span = self.span_from(self.token_start);
+                        self.errors.push(CompileError::UnterminatedString { span });
+                        return Some(Token::String(t))
                     },
                     // シンボルの場合
                     '{' | '}' | '(' | ')' | '[' | ']' | '.' | ',' | ';' | '+' | 
@@ -113,49 +282,44 @@ impl<R: Read + Seek> Iterator for Tokenizer<R> {
                         let mut t = String::new();
                         t.push(c);
 
-                        while let Some(c) = self.stream.read_char() {
-                            match c {
-                                // 数字の場合はトークンに追加する
-                                '0'..='9' => t.push(c),
-                                // それ以外の文字の場合は読まなかったことに
-                                // してトークンを排出する
-                                _ => {
-                                    let _ = self.stream.seek(
-                                        SeekFrom::Current(-1));
-                                    return Some(Token::new(t).unwrap())
-                                }
-                            }
+                        // 次の文字が数字の間はトークンに追加する
+                        while let Some(Ok(c @ '0'..='9')) = self.stream.peek_char() {
+                            self.stream.read_char();
+                            t.push(c);
                         }
-                        // 終端まで読み終えた場合はトークンを排出する
-                        return Some(Token::new(t).unwrap())
+                        // 数字以外の文字か終端に来たらトークンを排出する
+                        return Some(self.integer_token(t))
                     },
                     // アルファベットまたはアンダースコアの場合
                     'A'..='Z' | 'a'..='z' | '_' => {
                         let mut t = String::new();
                         t.push(c);
                         
-                        while let Some(c) = self.stream.read_char() {
-                            match c {
-                                // アルファベット、アンダースコア、数字の
-                                // 場合はトークンに追加する
-                                'A'..='Z' | 'a'..='z' | '_' | '0'..='9' => {
-                                    t.push(c)
-                                },
-                                // それ以外の文字の場合は読まなかったことに
-                                // してトークンを排出する
-                                _ => {
-                                    let _ = self.stream.seek(
-                                        SeekFrom::Current(-1));
-                                    return Some(Token::new(t).unwrap())
-                                }
-                            }
+                        // 次の文字がアルファベット、アンダースコア、数字の
+                        // 間はトークンに追加する
+                        while let Some(Ok(c @ ('A'..='Z' | 'a'..='z' | '_' | '0'..='9')))
+                            = self.stream.peek_char() {
+                            self.stream.read_char();
+                            t.push(c);
                         }
-                        // 終端まで読み終えた場合はトークンを排出する
+                        // それ以外の文字か終端に来たらトークンを排出する
                         return Some(Token::new(t).unwrap())
                     },
-                    // どれにも合致しない文字は無視する
-                    _ => (),
+                    // 空白は飛ばす
+                    c if c.is_whitespace() => (),
+                    // どれにも合致しない文字はエラーにして読み飛ばす。
+                    // lenientのときは警告にする
+                    _ => {
+                        let span = self.span_from(self.token_start);
+                        let e = CompileError::UnknownCharacter { found: c, span };
+                        if self.lenient {
+                            self.warnings.push(e);
+                        } else {
+                            self.errors.push(e);
+                        }
+                    },
                 },
+                Matches::Invalid(_) => self.invalid_utf8(),
                 // 終端まで読み終えたらNoneを返すする
                 Matches::None => return None
             }
@@ -168,102 +332,152 @@ impl<R: Read + Seek> Iterator for Tokenizer<R> {
 enum Matches<'a> {
     Str(&'a str),
     Char(char),
+    /// UTF-8として正しくないバイト
+    Invalid(u8),
     None
 }
 
-trait MyRead {
-    /// ストリームから1byteだけ読み出す
-    fn read_byte(&mut self) -> Option<u8>;
-    /// ストリームから1文字だけ読み出す
-    fn read_char(&mut self) -> Option<char>;
-    /// ストリームの先頭から文字を読んで引数の文字列にマッチするものがあれば
-    /// それを返す。いずれの文字列にもマッチしなければ先頭の一文字を返す
-    fn matches<'a>(&mut self, s_list: &[&'a str]) -> Matches<'a>;
+/// 先読みのできるリーダー。読み戻しにシークを使わず、先読みしたバイトを
+/// バッファに残しておく
+struct PeekReader<R> {
+    stream: BufReader<R>,
+    // 先読みしたがまだ読み進めていないバイト
+    buffer: VecDeque<u8>,
+    /// 読み進めたバイト数
+    offset: usize,
+    /// 読み進めた文字数。UTF-8として正しくないバイトは1文字と数える
+    chars: usize,
+    /// 読み出せなかったときのエラー。Tokenizerが取り出して報告する
+    error: Option<io::Error>,
+    /// 読み出せなかったらtrueにし、それ以降は終端として扱う
+    failed: bool,
 }
 
-impl<R: Read + Seek> MyRead for BufReader<R> {
+impl<R: Read> PeekReader<R> {
+    fn new(stream: R) -> PeekReader<R> {
+        PeekReader {
+            stream: BufReader::new(stream),
+            buffer: VecDeque::new(),
+            offset: 0,
+            chars: 0,
+            error: None,
+            failed: false,
+        }
+    }
+
+    /// n番目のバイトを読み進めずに返す。nは0から始まる
+    fn peek_byte(&mut self, n: usize) -> Option<u8> {
+        while self.buffer.len() <= n {
+            if self.failed {
+                return None
+            }
+            let mut c = [0;1];
+            match self.stream.read(&mut c) {
+                Ok(1) => self.buffer.push_back(c[0]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                // 読み出せないときはエラーを残して終端として扱う
+                Err(e) => {
+                    self.error = Some(e);
+                    self.failed = true;
+                    return None
+                },
+                // 終端ではNoneを返す
+                _ => return None
+            }
+        }
+
+        Some(self.buffer[n])
+    }
+
     /// ストリームから1byteだけ読み出す
     fn read_byte(&mut self) -> Option<u8> {
-        let mut c = [0;1];
-        if self.read(&mut c).unwrap_or(0) == 0 {
-            return None
+        let b = self.peek_byte(0)?;
+        self.buffer.pop_front();
+        self.offset += 1;
+        Some(b)
+    }
+
+    /// 先頭の1文字を読み進めずに返す。
+    /// UTF-8として正しくない場合は先頭のバイトをErrで返す
+    fn peek_char(&mut self) -> Option<Result<char, u8>> {
+        let first = self.peek_byte(0)?;
+        // 1バイト目から文字のバイト数がわかる
+        let len = match first {
+            0x00..=0x7f => return Some(Ok(first as char)),
+            0xc2..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf4 => 4,
+            _ => return Some(Err(first))
+        };
+
+        let mut bytes = vec![first];
+        for i in 1..len {
+            match self.peek_byte(i) {
+                Some(b) => bytes.push(b),
+                None => break
+            }
         }
 
-        Some(c[0])
+        match std::str::from_utf8(&bytes) {
+            Ok(s) => s.chars().next().map(Ok),
+            Err(_) => Some(Err(first))
+        }
     }
 
-    /// ストリームから1文字だけ読み出す
-    fn read_char(&mut self) -> Option<char> {
-        let mut c = [0;1];
-        if self.read(&mut c).unwrap_or(0) == 0 {
-            return None
+    /// ストリームから1文字だけ読み出す。
+    /// UTF-8として正しくない場合はそのバイトだけを読み、Errで返す
+    fn read_char(&mut self) -> Option<Result<char, u8>> {
+        let c = self.peek_char()?;
+        let len = match c {
+            Ok(c) => c.len_utf8(),
+            Err(_) => 1
+        };
+        for _ in 0..len {
+            self.read_byte();
         }
+        self.chars += 1;
 
-        Some(c[0] as char)
+        Some(c)
     }
 
     /// ストリームの先頭から文字を読んで引数の文字列にマッチするものがあれば
     /// それを返す。いずれの文字列にもマッチしなければ先頭の一文字を返す
     fn matches<'a>(&mut self, s_list: &[&'a str]) -> Matches<'a> {
-        /*
-        読み出したbytesより文字列のバイトの方が多ければ、足りない分を読み出す
-        リターンするときは読み過ぎた分をシークで戻す
-        */
-        let mut bytes: Vec<u8> = Vec::new();
-
-        'outer: for s in s_list {
-            for (i, b) in s.as_bytes().iter().enumerate() {
-                // 足りないときは読み出す
-                if bytes.get(i) == None {
-                    if let Some(c) = self.read_byte() {
-                        bytes.push(c);
-                    } else {
-                        // 読み出せないときはこのループを終了して次の文字列へ
-                        continue 'outer;
-                    }
-                }
-
-                if bytes[i] != *b {
-                    // 一文字でも違うならこのループを終了する
-                    continue 'outer;
+        for s in s_list {
+            let matched = s.bytes().enumerate()
+                .all(|(i, b)| self.peek_byte(i) == Some(b));
+            if matched {
+                for _ in 0..s.len() {
+                    self.read_byte();
                 }
+                self.chars += s.chars().count();
+                return Matches::Str(s)
             }
-
-            // 最後まで一致したらそれを返す
-            // しかし、その前に読み過ぎた分をシークで戻す
-            let len = bytes.len() - s.len();
-            if 0 < len {
-                let _ = self.seek(SeekFrom::Current(-(len as i64)));
-            }
-            return Matches::Str(s)
         }
 
-        // 一文字も読み出していないときはNoneを返す
-        // streamから文字を読み出せなかったということ
-        if bytes.len() == 0 {
-            return Matches::None
-        }
-        
-        // 読みすぎた分を戻して1文字だけ返す
-        let len = bytes.len() - 1;
-        if 0 < len {
-            let _ = self.seek(SeekFrom::Current(-(len as i64)));
+        // ストリームから文字を読み出せなかったときはNoneを返す
+        match self.read_char() {
+            Some(Ok(c)) => Matches::Char(c),
+            Some(Err(b)) => Matches::Invalid(b),
+            None => Matches::None
         }
-        
-        return Matches::Char(bytes[0] as char)
     }
 }
 
 
 #[cfg(test)]
 mod test {
-    use std::io::BufReader;
+    use std::io;
     use std::io::Cursor;
+    use std::io::Read;
 
     use super::Tokenizer;
     use super::token::Token;
+    use super::token::Keyword;
+    use super::Span;
+    use super::CompileError;
     use super::Matches;
-    use super::MyRead;
+    use super::PeekReader;
 
     #[test]
     fn test_tokenizer_next() {
@@ -309,7 +523,7 @@ mod test {
         "#);
         let r = ["aiueo", "*", "aiueo2"];
         assert_eq!(
-            Tokenizer::new(c).into_iter().map(|t| t).collect::<Vec<Token>>(),
+            Tokenizer::new(c).collect::<Vec<Token>>(),
             r.iter().map(|s| Token::new(s.to_string()).unwrap()).collect::<Vec<Token>>()
         );
 
@@ -324,7 +538,7 @@ mod test {
             "{", "return", "1", "*", "2", "}"
         ];
         assert_eq!(
-            Tokenizer::new(cursor).into_iter().map(|t| t).collect::<Vec<Token>>(),
+            Tokenizer::new(cursor).collect::<Vec<Token>>(),
             r.iter().map(|s| Token::new(s.to_string()).unwrap()).collect::<Vec<Token>>()
         );
 
@@ -342,7 +556,7 @@ mod test {
             ";", "let", "a", ";", "let", "b", ";", "}"
         ];
         assert_eq!(
-            Tokenizer::new(cursor).into_iter().map(|t| t).collect::<Vec<Token>>(),
+            Tokenizer::new(cursor).collect::<Vec<Token>>(),
             r.iter().map(|s| Token::new(s.to_string()).unwrap()).collect::<Vec<Token>>()
         );
     } 
@@ -366,28 +580,229 @@ mod test {
     }
 
     #[test]
-    fn test_bufreader_matches() {
-        let mut bufreader = BufReader::new(Cursor::new(""));
-        assert_eq!(bufreader.matches(&["aiueo"]), Matches::None);
+    fn test_tokenizer_current_span() {
+        // コメントの中の改行も行として数える
+        let mut t = Tokenizer::with_file(Cursor::new("/* a\n b */ x\n// c\n  yz"),
+                                         "A.jack");
+        t.advance();
+        assert_eq!(t.current_span(), &Span {
+            file: Some("A.jack".into()),
+            start_line: 2, start_col: 7, end_line: 2, end_col: 8, byte_range: 11..12,
+        });
+
+        t.advance();
+        let s = t.current_span();
+        assert_eq!((s.start_line, s.start_col, s.end_line, s.end_col), (4, 3, 4, 5));
+        assert_eq!(s.byte_range, 20..22);
+
+        // 最後まで読んだら終端の位置になる
+        assert_eq!(t.advance(), None);
+        assert_eq!(t.current_span().byte_range, 22..22);
+    }
+
+    #[test]
+    fn test_tokenizer_utf8() {
+        // 日本語のコメントや文字列をUTF-8として読む
+        let mut t = Tokenizer::new(Cursor::new(
+            "/* コメント\n */ // 行コメント\nlet s = \"あいう\";é"));
+        assert_eq!(t.next(), Some(Token::Keyword(Keyword::Let)));
+        assert_eq!(t.current_span().start_line, 3);
+        t.next();
+        t.next();
+        assert_eq!(t.next(), Some(Token::String("あいう".to_string())));
+        assert_eq!(t.next(), Some(Token::Symbol(';')));
+        // 文字列とコメントの外のASCII以外の文字はエラーになる
+        assert_eq!(t.next(), None);
+        match t.take_errors().pop() {
+            Some(CompileError::UnknownCharacter { found: 'é', span }) => {
+                // 列番号は文字数で、byte_rangeはバイト数で数える
+                assert_eq!((span.start_col, span.end_col), (15, 16));
+                assert_eq!(span.byte_range.len(), 2);
+            },
+            e => panic!("{:?}", e)
+        }
+
+        // 識別子の直後にASCII以外の文字があっても正しく戻す
+        let mut t = Tokenizer::new(Cursor::new("abcあ1"));
+        t.set_lenient(true);
+        assert_eq!(t.next(), Some(Token::Identifier("abc".to_string())));
+        assert_eq!(t.next(), Some(Token::Integer(1)));
+
+        // UTF-8として正しくないバイトはエラーにして読み飛ばす
+        let mut t = Tokenizer::new(Cursor::new(b"ab \"c\xff\" \xfe d".to_vec()));
+        assert_eq!(t.next(), Some(Token::Identifier("ab".to_string())));
+        assert_eq!(t.next(), Some(Token::String("c".to_string())));
+        assert_eq!(t.next(), Some(Token::Identifier("d".to_string())));
+        assert_eq!(t.next(), None);
+        let errors = t.take_errors();
+        assert_eq!(errors[0], CompileError::InvalidUtf8 {
+            span: Span {
+                start_line: 1, start_col: 6, end_line: 1, end_col: 7, byte_range: 5..6,
+                ..Span::default()
+            }
+        });
+        assert_eq!(errors[1].span().unwrap().byte_range, 8..9);
+    }
+
+    #[test]
+    fn test_tokenizer_non_seekable() {
+        // &[u8]やChainはシークできない
+        let input = "let x".as_bytes().chain(" = 12;".as_bytes());
+        let tokens: Vec<Token> = Tokenizer::new(input).collect();
+        assert_eq!(tokens, vec![
+            Token::Keyword(Keyword::Let),
+            Token::Identifier("x".to_string()),
+            Token::Symbol('='),
+            Token::Integer(12),
+            Token::Symbol(';'),
+        ]);
+    }
+
+    #[test]
+    fn test_tokenizer_take_errors() {
+        let mut t = Tokenizer::new(Cursor::new("a \"bc"));
+        assert_eq!(t.next(), Some(Token::Identifier("a".to_string())));
+        // 閉じていない文字列もそこまでを文字列として返す
+        assert_eq!(t.next(), Some(Token::String("bc".to_string())));
+        assert_eq!(t.next(), None);
+        // 文字列の始まりから終端までを指す
+        assert_eq!(t.take_errors(), vec![CompileError::UnterminatedString {
+            span: Span {
+                start_line: 1, start_col: 3, end_line: 1, end_col: 6, byte_range: 2..5,
+                ..Span::default()
+            }
+        }]);
+
+        // 改行を含む文字列は改行の手前までを指し、次の行から読み続ける
+        let mut t = Tokenizer::new(Cursor::new("\"ab\r\ncd"));
+        assert_eq!(t.next(), Some(Token::String("ab".to_string())));
+        assert_eq!(t.next(), Some(Token::Identifier("cd".to_string())));
+        assert_eq!(t.take_errors(), vec![CompileError::UnterminatedString {
+            span: Span {
+                start_line: 1, start_col: 1, end_line: 1, end_col: 4, byte_range: 0..3,
+                ..Span::default()
+            }
+        }]);
+
+        let mut t = Tokenizer::new(Cursor::new("32768 32769 1"));
+        assert_eq!(t.next(), Some(Token::Integer(32768)));
+        // 大きすぎる数は0として読み続ける
+        assert_eq!(t.next(), Some(Token::Integer(0)));
+        assert_eq!(t.next(), Some(Token::Integer(1)));
+        assert_eq!(t.take_errors(), vec![CompileError::IntegerOverflow {
+            value: "32769".to_string(),
+            span: Span {
+                start_line: 1, start_col: 7, end_line: 1, end_col: 12, byte_range: 6..11,
+                ..Span::default()
+            }
+        }]);
+
+        // usizeに収まらない数も同じエラーになる
+        let mut t = Tokenizer::new(Cursor::new("99999999999999999999999"));
+        assert_eq!(t.next(), Some(Token::Integer(0)));
+        assert!(matches!(t.take_errors()[..], [CompileError::IntegerOverflow { .. }]));
+
+        let mut t = Tokenizer::new(Cursor::new("a /* b"));
+        t.next();
+        assert_eq!(t.next(), None);
+        assert!(matches!(t.take_errors()[..], [CompileError::UnterminatedComment { .. }]));
+
+        let mut t = Tokenizer::new(Cursor::new("a"));
+        t.next();
+        assert_eq!(t.next(), None);
+        assert_eq!(t.take_errors(), vec![]);
+    }
+
+    // 途中から読み出せなくなるリーダー
+    struct Broken<'a>(&'a [u8]);
+
+    impl Read for Broken<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Err(io::Error::other("broken pipe"))
+            }
+            self.0.read(buf)
+        }
+    }
+
+    #[test]
+    fn test_tokenizer_read_error() {
+        // 読み出せなくなったら終端として扱い、エラーを1つだけ残す
+        let mut t = Tokenizer::with_file(Broken(b"let x"), "<stdin>");
+        assert_eq!(t.next(), Some(Token::Keyword(Keyword::Let)));
+        assert_eq!(t.next(), Some(Token::Identifier("x".to_string())));
+        assert_eq!(t.next(), None);
+        assert_eq!(t.next(), None);
+        assert_eq!(t.take_errors(), vec![CompileError::Io {
+            path: "<stdin>".to_string(),
+            kind: io::ErrorKind::Other,
+            message: "broken pipe".to_string(),
+        }]);
+    }
+
+    #[test]
+    fn test_tokenizer_unknown_character() {
+        // エラーにした文字は読み飛ばし、後のエラーも見つける
+        let mut t = Tokenizer::new(Cursor::new("a\t!= b @ 40000"));
+        assert_eq!(t.next(), Some(Token::Identifier("a".to_string())));
+        assert_eq!(t.next(), Some(Token::Symbol('=')));
+        assert_eq!(t.next(), Some(Token::Identifier("b".to_string())));
+        assert_eq!(t.next(), Some(Token::Integer(0)));
+        assert_eq!(t.next(), None);
+        let errors = t.take_errors();
+        assert_eq!(errors[0], CompileError::UnknownCharacter {
+            found: '!',
+            span: Span {
+                start_line: 1, start_col: 3, end_line: 1, end_col: 4, byte_range: 2..3,
+                ..Span::default()
+            }
+        });
+        assert!(matches!(errors[1], CompileError::UnknownCharacter { found: '@', .. }));
+        assert!(matches!(errors[2], CompileError::IntegerOverflow { .. }));
+
+        // lenientのときは警告にして読み飛ばす
+        let mut t = Tokenizer::new(Cursor::new("a @#$!% b"));
+        t.set_lenient(true);
+        assert_eq!(t.next(), Some(Token::Identifier("a".to_string())));
+        assert_eq!(t.next(), Some(Token::Identifier("b".to_string())));
+        assert_eq!(t.take_errors(), vec![]);
+        let warnings = t.take_warnings();
+        assert_eq!(warnings.len(), 5);
+        assert_eq!(warnings[0].to_string(), "1:3: unknown start of token: @");
+    }
+
+    #[test]
+    fn test_peek_reader_matches() {
+        let mut reader = PeekReader::new(Cursor::new(""));
+        assert_eq!(reader.matches(&["aiueo"]), Matches::None);
         
-        let mut bufreader = BufReader::new(Cursor::new("aiueo"));
-        assert_eq!(bufreader.matches(&["aiueo"]), Matches::Str("aiueo"));
-
-        let mut bufreader = BufReader::new(Cursor::new("aiueokakikukeko"));
-        bufreader.matches(&["aiueo"]);
-        assert_eq!(bufreader.matches(&["kakikukeko"]), Matches::Str("kakikukeko"));
-        assert_eq!(bufreader.matches(&["a"]), Matches::None);
-
-        let mut bufreader = BufReader::new(Cursor::new("aiueokakikukeko"));
-        assert_eq!(bufreader.matches(&["//"]), Matches::Char('a'));
-        assert_eq!(bufreader.matches(&["//"]), Matches::Char('i'));
-
-        let mut bufreader = BufReader::new(Cursor::new("aiueo"));
-        assert_eq!(bufreader.matches(&["aiueokakikukeko"]), Matches::Char('a'));        
-        assert_eq!(bufreader.matches(&["aiueokakikukeko"]), Matches::Char('i'));
-        assert_eq!(bufreader.matches(&["aiueokakikukeko"]), Matches::Char('u'));
-        assert_eq!(bufreader.matches(&["aiueokakikukeko"]), Matches::Char('e'));
-        assert_eq!(bufreader.matches(&["aiueokakikukeko"]), Matches::Char('o')); 
-        assert_eq!(bufreader.matches(&["aiueokakikukeko"]), Matches::None);
+        let mut reader = PeekReader::new(Cursor::new("aiueo"));
+        assert_eq!(reader.matches(&["aiueo"]), Matches::Str("aiueo"));
+
+        let mut reader = PeekReader::new(Cursor::new("aiueokakikukeko"));
+        reader.matches(&["aiueo"]);
+        assert_eq!(reader.matches(&["kakikukeko"]), Matches::Str("kakikukeko"));
+        assert_eq!(reader.matches(&["a"]), Matches::None);
+
+        let mut reader = PeekReader::new(Cursor::new("aiueokakikukeko"));
+        assert_eq!(reader.matches(&["//"]), Matches::Char('a'));
+        assert_eq!(reader.matches(&["//"]), Matches::Char('i'));
+
+        let mut reader = PeekReader::new(Cursor::new("aiueo"));
+        assert_eq!(reader.matches(&["aiueokakikukeko"]), Matches::Char('a'));        
+        assert_eq!(reader.matches(&["aiueokakikukeko"]), Matches::Char('i'));
+        assert_eq!(reader.matches(&["aiueokakikukeko"]), Matches::Char('u'));
+        assert_eq!(reader.matches(&["aiueokakikukeko"]), Matches::Char('e'));
+        assert_eq!(reader.matches(&["aiueokakikukeko"]), Matches::Char('o')); 
+        assert_eq!(reader.matches(&["aiueokakikukeko"]), Matches::None);
+
+        let mut reader = PeekReader::new(Cursor::new("あ/*"));
+        assert_eq!(reader.matches(&["/*"]), Matches::Char('あ'));
+        assert_eq!(reader.matches(&["/*"]), Matches::Str("/*"));
+
+        let mut reader = PeekReader::new(Cursor::new(b"\xe3\x81a".to_vec()));
+        assert_eq!(reader.matches(&["//"]), Matches::Invalid(0xe3));
+        assert_eq!(reader.matches(&["//"]), Matches::Invalid(0x81));
+        assert_eq!(reader.matches(&["//"]), Matches::Char('a'));
     }
 }
\ No newline at end of file
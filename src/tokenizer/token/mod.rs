@@ -1,8 +1,12 @@
 //! Tokenは１つのtokenに対応する
+use std::fmt;
 use std::str::FromStr;
-use std::string::ToString;
+
+/// 整数定数の最大値
+pub const MAX_INTEGER: usize = 32767;
 
 /// Tokenの種類の詳細は233ページに書いてある。Integerは0から32767までの整数。
+/// ただし-32768を書けるように、字句解析では32768も受け付ける
 #[derive(Debug, PartialEq)]
 pub enum Token {
     Keyword(Keyword),
@@ -38,9 +42,9 @@ pub enum Keyword {
     This
 }
 
-impl Keyword {
-    pub fn to_string(&self) -> String {
-        match self {
+impl fmt::Display for Keyword {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
             Keyword::Class => "class",
             Keyword::Method => "method",
             Keyword::Function => "function",
@@ -62,14 +66,15 @@ impl Keyword {
             Keyword::False => "false",
             Keyword::Null => "null",
             Keyword::This => "this",
-        }.to_string()
+        };
+        write!(f, "{}", s)
     }
 }
 
 impl Token {
     /// 引数の文字列を元に適切なTokenを返す。無効なtokenの場合はNoneを返す
     pub fn new(t: String) -> Option<Token> {
-        if t.len() == 0 {
+        if t.is_empty() {
             return None
         }
 
@@ -101,23 +106,24 @@ impl Token {
             "false" => Token::Keyword(Keyword::False),
             "null" => Token::Keyword(Keyword::Null),
             "this" => Token::Keyword(Keyword::This),
-            _ => match usize::from_str(&t) {
-                Ok(u) => Token::Integer(u),
-                _ => {
-                    let mut chars = t.chars();
-                    match (chars.next(), chars.last()) {
-                        // 最初と最後が"の場合は文字列とする
-                        (Some(c), Some(l)) if c == '"' && l == '"' => {
-                            if &t == "\"" {
-                                // "だけだった場合はNoneを返す
-                                return None
-                            }
-                            Token::String(
-                                t.get(1..t.len()-1).unwrap().to_string())
-                        },
-                        // いずれにも当てはまらないときはIdentifierとする
-                        _ => Token::Identifier(t)
-                    }
+            // 数字だけの文字列は整数とし、usizeに収まらないときはNoneを返す
+            _ if t.bytes().all(|b| b.is_ascii_digit()) => {
+                Token::Integer(usize::from_str(&t).ok()?)
+            },
+            _ => {
+                let mut chars = t.chars();
+                match (chars.next(), chars.last()) {
+                    // 最初と最後が"の場合は文字列とする
+                    (Some(c), Some(l)) if c == '"' && l == '"' => {
+                        if &t == "\"" {
+                            // "だけだった場合はNoneを返す
+                            return None
+                        }
+                        Token::String(
+                            t.get(1..t.len()-1).unwrap().to_string())
+                    },
+                    // いずれにも当てはまらないときはIdentifierとする
+                    _ => Token::Identifier(t)
                 }
             }
         };
@@ -128,29 +134,29 @@ impl Token {
     /// テスト用にxmlで書き出す関数
     pub fn to_xml(&self) -> String {
         match self {
-            Token::Keyword(t) => format!("<keyword> {} </keyword>", t.to_string()),
-            Token::Symbol(t) if *t == '<' => format!("<symbol> &lt; </symbol>"),
-            Token::Symbol(t) if *t == '>' => format!("<symbol> &gt; </symbol>"),
-            Token::Symbol(t) if *t == '&' => format!("<symbol> &amp; </symbol>"),
+            Token::Keyword(t) => format!("<keyword> {} </keyword>", t),
+            Token::Symbol(t) if *t == '<' => "<symbol> &lt; </symbol>".to_string(),
+            Token::Symbol(t) if *t == '>' => "<symbol> &gt; </symbol>".to_string(),
+            Token::Symbol(t) if *t == '&' => "<symbol> &amp; </symbol>".to_string(),
             Token::Symbol(t) => format!("<symbol> {} </symbol>", t),
             Token::Integer(t) => format!("<integerConstant> {} </integerConstant>", t),
-            Token::String(t) => format!("<stringConstant> {} </stringConstant>", t),
+            Token::String(t) => {
+                // 文字列の中の<、>、&もエスケープする
+                let t = t.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+                format!("<stringConstant> {} </stringConstant>", t)
+            },
             Token::Identifier(t) => format!("<identifier> {} </identifier>", t),
         }
     }
 }
 
-impl ToString for Token {
-    fn to_string(&self) -> String {
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Token::Keyword(k) => k.to_string(),
-            Token::Symbol(c) => {
-                let mut s = String::new();
-                s.push(*c);
-                s
-            },
-            Token::Integer(i) => i.to_string(),
-            Token::String(s) | Token::Identifier(s) => s.clone()
+            Token::Keyword(k) => write!(f, "{}", k),
+            Token::Symbol(c) => write!(f, "{}", c),
+            Token::Integer(i) => write!(f, "{}", i),
+            Token::String(s) | Token::Identifier(s) => write!(f, "{}", s)
         }
     }
 }
@@ -170,6 +176,8 @@ mod test {
                    Some(Token::Integer(0)));
         assert_eq!(Token::new("20".to_string()), 
                    Some(Token::Integer(20)));
+        // usizeに収まらない数は識別子にしない
+        assert_eq!(Token::new("99999999999999999999999".to_string()), None);
         assert_eq!(Token::new("\"aiueo\"".to_string()), 
                    Some(Token::String("aiueo".to_string())));
         assert_eq!(Token::new("\"\"".to_string()), 
@@ -177,4 +185,12 @@ mod test {
         assert_eq!(Token::new("aiueo".to_string()),
                    Some(Token::Identifier("aiueo".to_string())));
     }
+
+    #[test]
+    fn test_token_to_xml() {
+        assert_eq!(Token::Symbol('<').to_xml(), "<symbol> &lt; </symbol>");
+        assert_eq!(Token::Integer(3).to_xml(), "<integerConstant> 3 </integerConstant>");
+        assert_eq!(Token::String("a < b && c > d".to_string()).to_xml(),
+                   "<stringConstant> a &lt; b &amp;&amp; c &gt; d </stringConstant>");
+    }
 }
\ No newline at end of file
@@ -0,0 +1,303 @@
+//! TypeCheckerは構文木の式の型を調べ、型の合わない代入や条件、呼び出しを見つける。
+//! Jackは型を確かめない言語なので、--strict-typesを指定したときだけ使う。
+//! 型がわからない式(nullやRegistryにないクラスのサブルーチンの戻り値など)は
+//! 確かめない。
+//! intとchar、Arrayと他のクラスは互いに代入できるものとする
+
+use std::mem;
+
+use super::ast::*;
+use super::span::Span;
+use super::symbol_table::SymbolTable;
+use super::error::CompileError;
+use super::signature::{Registry, Signature};
+
+
+pub struct TypeChecker<'a> {
+    registry: &'a Registry,
+    class: &'a Class,
+    symbol_table: SymbolTable,
+    // 確かめているサブルーチンの戻り値の型。voidのときはNone
+    return_type: Option<&'a Type>,
+    errors: Vec<CompileError>,
+}
+
+// foundの値をexpectedの型の変数に代入できるか
+fn assignable(expected: &Type, found: &Type) -> bool {
+    match (expected, found) {
+        (a, b) if a == b => true,
+        (Type::Int, Type::Char) | (Type::Char, Type::Int) => true,
+        // Arrayはどのオブジェクトも指せる
+        (Type::Class(a), Type::Class(b)) => a == "Array" || b == "Array",
+        _ => false
+    }
+}
+
+fn is_primitive(ty: &Type) -> bool {
+    matches!(ty, Type::Int | Type::Char | Type::Boolean)
+}
+
+fn is_numeric(ty: &Type) -> bool {
+    matches!(ty, Type::Int | Type::Char)
+}
+
+impl<'a> TypeChecker<'a> {
+    /// registryは呼び出すサブルーチンの引数と戻り値の型を調べるのに使う
+    pub fn new(registry: &'a Registry, class: &'a Class) -> TypeChecker<'a> {
+        TypeChecker {
+            registry,
+            class,
+            symbol_table: SymbolTable::new(),
+            return_type: None,
+            errors: Vec::new(),
+        }
+    }
+
+    /// クラスの型を確かめる。変数は全て解決できるものとする。
+    /// エラーがあればソースコードの順に返す
+    pub fn check_class(mut self) -> Result<(), Vec<CompileError>> {
+        let class = self.class;
+        self.symbol_table.define_class_vars(class);
+        for subroutine_dec in &class.subroutine_decs {
+            self.check_subroutine(subroutine_dec);
+        }
+
+        if self.errors.is_empty() {
+            return Ok(())
+        }
+        let mut errors = mem::take(&mut self.errors);
+        errors.sort_by_key(|e| e.span().map(|s| s.byte_range.start));
+        Err(errors)
+    }
+
+    // 変数の型を返す
+    fn type_of(&self, name: &Identifier) -> Option<Type> {
+        self.symbol_table.type_of(&name.name).map(Type::from_name)
+    }
+
+    // 型が合わなければエラーを記録する。型がわからないときは何もしない
+    fn expect(&mut self, expected: &Type, found: Option<Type>, span: &Span) {
+        match found {
+            Some(found) if !assignable(expected, &found) => {
+                self.errors.push(CompileError::TypeMismatch {
+                    expected: expected.name().to_string(),
+                    found: found.name().to_string(),
+                    span: span.clone(),
+                });
+            },
+            _ => ()
+        }
+    }
+
+    fn check_subroutine(&mut self, subroutine_dec: &'a SubroutineDec) {
+        self.symbol_table.enter_subroutine(&self.class.name.name, subroutine_dec);
+        self.return_type = subroutine_dec.return_type.as_ref();
+        self.check_statements(&subroutine_dec.statements);
+    }
+
+    fn check_statements(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.check_statement(statement);
+        }
+    }
+
+    fn check_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Let { name, index, value, .. } => {
+                let found = self.check_expression(value);
+                match index {
+                    // 配列の要素の型はわからないので確かめない
+                    Some(index) => {
+                        self.check_array(name);
+                        self.check_expression(index);
+                    },
+                    None => if let Some(ty) = self.type_of(name) {
+                        self.expect(&ty, found, &value.span);
+                    },
+                }
+            },
+            Statement::If { condition, then_statements, else_statements, .. } => {
+                self.check_condition(condition);
+                self.check_statements(then_statements);
+                if let Some(else_statements) = else_statements {
+                    self.check_statements(else_statements);
+                }
+            },
+            Statement::While { condition, statements, .. } => {
+                self.check_condition(condition);
+                self.check_statements(statements);
+            },
+            Statement::Do { call, .. } => {
+                self.check_subroutine_call(call);
+            },
+            Statement::Return { value: Some(value), .. } => {
+                let found = self.check_expression(value);
+                if let Some(ty) = self.return_type {
+                    self.expect(ty, found, &value.span);
+                }
+            },
+            Statement::Return { value: None, .. } => (),
+        }
+    }
+
+    fn check_condition(&mut self, condition: &Expression) {
+        let found = self.check_expression(condition);
+        self.expect(&Type::Boolean, found, &condition.span);
+    }
+
+    // 添字で参照する変数がArrayか確かめる
+    fn check_array(&mut self, name: &Identifier) {
+        if let Some(ty) = self.type_of(name) {
+            if ty != Type::Class("Array".to_string()) {
+                self.errors.push(CompileError::NotAnArray {
+                    name: name.name.clone(),
+                    ty: ty.name().to_string(),
+                    span: name.span.clone(),
+                });
+            }
+        }
+    }
+
+    // 式の型を返す。わからないときはNoneを返す
+    fn check_expression(&mut self, expression: &Expression) -> Option<Type> {
+        let mut ty = self.check_term(&expression.term);
+        for (op, term) in &expression.rest {
+            let right = self.check_term(term);
+            ty = match op {
+                BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div => Some(Type::Int),
+                BinaryOp::Lt | BinaryOp::Gt | BinaryOp::Eq => Some(Type::Boolean),
+                // &と|は真偽値どうしなら真偽値、数どうしならビットごとの演算
+                BinaryOp::And | BinaryOp::Or => match (ty, right) {
+                    (Some(Type::Boolean), Some(Type::Boolean)) => Some(Type::Boolean),
+                    (Some(l), Some(r)) if is_numeric(&l) && is_numeric(&r) => Some(Type::Int),
+                    _ => None
+                },
+            };
+        }
+        ty
+    }
+
+    fn check_term(&mut self, term: &Term) -> Option<Type> {
+        match term {
+            Term::IntegerConstant { .. } => Some(Type::Int),
+            Term::StringConstant { .. } => Some(Type::Class("String".to_string())),
+            Term::KeywordConstant { value, .. } => match value {
+                KeywordConstant::True | KeywordConstant::False => Some(Type::Boolean),
+                KeywordConstant::This => Some(Type::Class(self.class.name.name.clone())),
+                // nullはどの型にも代入できる
+                KeywordConstant::Null => None,
+            },
+            Term::VarName(name) => self.type_of(name),
+            Term::ArrayAccess { name, index, .. } => {
+                self.check_array(name);
+                self.check_expression(index);
+                None
+            },
+            Term::SubroutineCall(call) => self.check_subroutine_call(call),
+            Term::Parenthesized { expression, .. } => self.check_expression(expression),
+            Term::Unary { op, term, .. } => {
+                let ty = self.check_term(term);
+                match (op, ty) {
+                    (UnaryOp::Neg, _) => Some(Type::Int),
+                    (UnaryOp::Not, Some(Type::Boolean)) => Some(Type::Boolean),
+                    (UnaryOp::Not, Some(ty)) if is_numeric(&ty) => Some(Type::Int),
+                    (UnaryOp::Not, _) => None,
+                }
+            },
+        }
+    }
+
+    // 引数の型を確かめ、戻り値の型を返す
+    fn check_subroutine_call(&mut self, call: &SubroutineCall) -> Option<Type> {
+        let class_name = match &call.receiver {
+            Some(r) => match self.type_of(r) {
+                Some(ty) if is_primitive(&ty) => {
+                    self.errors.push(CompileError::MethodOnPrimitive {
+                        name: r.name.clone(),
+                        ty: ty.name().to_string(),
+                        span: r.span.clone(),
+                    });
+                    None
+                },
+                Some(ty) => Some(ty.name().to_string()),
+                // 変数でなければクラス名
+                None => Some(r.name.clone()),
+            },
+            None => Some(self.class.name.name.clone()),
+        };
+        let registry = self.registry;
+        let signature: Option<&Signature> = class_name
+            .and_then(|c| registry.get(&c, &call.name.name));
+
+        let arguments: Vec<Option<Type>> = call.arguments.iter()
+            .map(|a| self.check_expression(a))
+            .collect();
+        let signature = signature?;
+        let parameters = signature.parameters.iter().zip(&call.arguments).zip(arguments);
+        for ((parameter, argument), found) in parameters {
+            self.expect(parameter, found, &argument.span);
+        }
+        signature.return_type.clone()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::TypeChecker;
+    use crate::signature::Registry;
+    use crate::error::CompileError;
+    use crate::compilation_engine::parse_str;
+
+    // エラーの説明と行番号の一覧。他のクラスとしてGを使える
+    fn errors(source: &str) -> Vec<(String, usize)> {
+        let class = parse_str(source).unwrap();
+        let mut registry = Registry::from_class(&class);
+        registry.add_class(&parse_str("class G { method int run(int n) { return n; } }").unwrap());
+        TypeChecker::new(&registry, &class).check_class().err().unwrap_or_default().iter()
+            .map(|e: &CompileError| (e.message(), e.span().unwrap().start_line))
+            .collect()
+    }
+
+    #[test]
+    fn test_check_class() {
+        assert_eq!(errors("class A {
+            field Array a;
+            field String s;
+            method int f(int i, char c, boolean b) {
+                let i = c + a[0];
+                let s = \"x\";
+                let a = s;
+                let s = null;
+                if ((i < 1) & ~b) { let i = f(c, i, true); }
+                while (b | (i = 0)) { do s.appendChar(c); }
+                return Math.max(i, 1);
+            }
+        }"), Vec::new());
+
+        assert_eq!(errors("class A {
+            field String s;
+            method int f(int i, boolean b) {
+                var G g;
+                let i = \"x\";
+                if (i) { let b = i + 1; }
+                while (f(s, 1)) { let s[0] = 1; }
+                do i.foo();
+                let b = g.run(s);
+                return b;
+            }
+        }"), vec![
+            ("mismatched types: expected int, found String".to_string(), 5),
+            ("mismatched types: expected boolean, found int".to_string(), 6),
+            ("mismatched types: expected boolean, found int".to_string(), 6),
+            ("mismatched types: expected boolean, found int".to_string(), 7),
+            ("mismatched types: expected int, found String".to_string(), 7),
+            ("mismatched types: expected boolean, found int".to_string(), 7),
+            ("cannot index 's' of type String".to_string(), 7),
+            ("cannot call a method on 'i' of type int".to_string(), 8),
+            ("mismatched types: expected boolean, found int".to_string(), 9),
+            ("mismatched types: expected int, found String".to_string(), 9),
+            ("mismatched types: expected int, found boolean".to_string(), 10),
+        ]);
+    }
+}
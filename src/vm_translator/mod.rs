@@ -0,0 +1,505 @@
+//! VMTranslatorはvmコードをHackのアセンブリに変換する。
+//! 変換の方法は7章と8章に書いてある。
+//! 複数のクラスを1つのプログラムにするときは、最初にbootstrapでブートストラップの
+//! コードを書いてから全てのクラスのvmコードを続けて変換する。
+//! プログラムにはどこにも定義されていない関数を呼び出すところがあってはいけない
+
+use std::collections::HashSet;
+
+/// vmコードをアセンブリにできないときのエラー
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TranslateError {
+    /// vmコードの1行を読めなかった。lineは1から始まる
+    InvalidLine { line: usize, message: String },
+    /// プログラムのどのクラスにも定義されていない関数を呼び出している
+    UndefinedFunction { name: String },
+}
+
+/// vmコードを読み、アセンブリを書き出す
+#[derive(Default)]
+pub struct VMTranslator {
+    output: String,
+    // 変換中の関数名。ラベルの名前に使う
+    function: String,
+    // 比較やcallのラベルを一意にするための番号
+    label_id: usize,
+    // 定義した関数の名前
+    functions: HashSet<String>,
+    // 呼び出した関数の名前。呼び出した順に並べる
+    calls: Vec<String>,
+}
+
+// セグメントのベースアドレスを持つシンボル
+fn base_symbol(segment: &str) -> Option<&'static str> {
+    match segment {
+        "local" => Some("LCL"),
+        "argument" => Some("ARG"),
+        "this" => Some("THIS"),
+        "that" => Some("THAT"),
+        _ => None
+    }
+}
+
+impl VMTranslator {
+    pub fn new() -> VMTranslator {
+        VMTranslator::default()
+    }
+
+    // アセンブリを1行書く
+    fn emit(&mut self, line: &str) {
+        self.output += line;
+        self.output.push('\n');
+    }
+
+    fn new_label(&mut self, name: &str) -> String {
+        self.label_id += 1;
+        format!("{}${}.{}", self.function, name, self.label_id)
+    }
+
+    // Dレジスタの値をスタックに積む
+    fn push_d(&mut self) {
+        self.emit("@SP\nAM=M+1\nA=A-1\nM=D");
+    }
+
+    // スタックから取り出した値をDレジスタに入れる
+    fn pop_d(&mut self) {
+        self.emit("@SP\nAM=M-1\nD=M");
+    }
+
+    // staticの変数のシンボル。クラス名.番号にする
+    fn static_symbol(&self, index: usize) -> String {
+        let class = self.function.split('.').next().unwrap_or_default();
+        format!("{}.{}", class, index)
+    }
+
+    // temp、pointer、staticのように直接アドレスがわかるセグメントのシンボル
+    fn direct_symbol(&self, segment: &str, index: usize) -> Option<String> {
+        match segment {
+            "temp" if index < 8 => Some(format!("R{}", 5 + index)),
+            "pointer" if index < 2 => Some(["THIS", "THAT"][index].to_string()),
+            "static" => Some(self.static_symbol(index)),
+            _ => None
+        }
+    }
+
+    fn push(&mut self, segment: &str, index: usize) -> Result<(), String> {
+        if segment == "constant" {
+            self.emit(&format!("@{}\nD=A", index));
+        } else if let Some(base) = base_symbol(segment) {
+            self.emit(&format!("@{}\nD=A\n@{}\nA=D+M\nD=M", index, base));
+        } else if let Some(symbol) = self.direct_symbol(segment, index) {
+            self.emit(&format!("@{}\nD=M", symbol));
+        } else {
+            return Err(format!("invalid segment: {} {}", segment, index))
+        }
+        self.push_d();
+        Ok(())
+    }
+
+    fn pop(&mut self, segment: &str, index: usize) -> Result<(), String> {
+        if let Some(base) = base_symbol(segment) {
+            // 書き込む先のアドレスをR13に置いておく
+            self.emit(&format!("@{}\nD=A\n@{}\nD=D+M\n@R13\nM=D", index, base));
+            self.pop_d();
+            self.emit("@R13\nA=M\nM=D");
+        } else if let Some(symbol) = self.direct_symbol(segment, index) {
+            self.pop_d();
+            self.emit(&format!("@{}\nM=D", symbol));
+        } else {
+            return Err(format!("invalid segment: {} {}", segment, index))
+        }
+        Ok(())
+    }
+
+    fn arithmetic(&mut self, command: &str) -> Result<(), String> {
+        match command {
+            // 2つの値をとる演算はyをDに、xをMに置いて計算する
+            "add" | "sub" | "and" | "or" => {
+                let comp = match command {
+                    "add" => "M=D+M",
+                    "sub" => "M=M-D",
+                    "and" => "M=D&M",
+                    _ => "M=D|M",
+                };
+                self.pop_d();
+                self.emit(&format!("A=A-1\n{}", comp));
+            },
+            "neg" => self.emit("@SP\nA=M-1\nM=-M"),
+            "not" => self.emit("@SP\nA=M-1\nM=!M"),
+            // 比較は真なら-1を、偽なら0を置く
+            "eq" | "gt" | "lt" => {
+                let jump = format!("J{}", command.to_uppercase());
+                let true_label = self.new_label("TRUE");
+                let end_label = self.new_label("END");
+                self.pop_d();
+                self.emit(&format!("A=A-1\nD=M-D\n@{}\nD;{}", true_label, jump));
+                self.emit(&format!("@SP\nA=M-1\nM=0\n@{}\n0;JMP", end_label));
+                self.emit(&format!("({})\n@SP\nA=M-1\nM=-1\n({})", true_label, end_label));
+            },
+            _ => return Err(format!("unknown command: {}", command))
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, name: &str, n_args: usize) {
+        if !self.calls.iter().any(|c| c == name) {
+            self.calls.push(name.to_string());
+        }
+        let return_label = self.new_label("ret");
+        self.emit(&format!("@{}\nD=A", return_label));
+        self.push_d();
+        for symbol in &["LCL", "ARG", "THIS", "THAT"] {
+            self.emit(&format!("@{}\nD=M", symbol));
+            self.push_d();
+        }
+        // ARG = SP - n_args - 5, LCL = SP
+        self.emit(&format!("@SP\nD=M\n@{}\nD=D-A\n@ARG\nM=D", n_args + 5));
+        self.emit("@SP\nD=M\n@LCL\nM=D");
+        self.emit(&format!("@{}\n0;JMP\n({})", name, return_label));
+    }
+
+    fn function(&mut self, name: &str, n_locals: usize) {
+        self.function = name.to_string();
+        self.functions.insert(name.to_string());
+        self.emit(&format!("({})", name));
+        for _ in 0..n_locals {
+            self.emit("D=0");
+            self.push_d();
+        }
+    }
+
+    fn return_(&mut self) {
+        // R13にフレームの位置を、R14に戻り先を置く
+        self.emit("@LCL\nD=M\n@R13\nM=D");
+        self.emit("@5\nA=D-A\nD=M\n@R14\nM=D");
+        // 戻り値を呼び出し元のスタックの先頭に置く
+        self.pop_d();
+        self.emit("@ARG\nA=M\nM=D\n@ARG\nD=M+1\n@SP\nM=D");
+        for symbol in &["THAT", "THIS", "ARG", "LCL"] {
+            self.emit(&format!("@R13\nAM=M-1\nD=M\n@{}\nM=D", symbol));
+        }
+        self.emit("@R14\nA=M\n0;JMP");
+    }
+
+    // vmコードを1行変換する
+    fn translate_line(&mut self, line: &str) -> Result<(), String> {
+        // コメントと前後の空白を取り除く
+        let line = line.split("//").next().unwrap_or_default().trim();
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        let number = |s: &str| s.parse::<usize>().map_err(|_| format!("invalid number: {}", s));
+        match words.as_slice() {
+            [] => (),
+            ["push", segment, index] => self.push(segment, number(index)?)?,
+            ["pop", segment, index] => self.pop(segment, number(index)?)?,
+            ["label", label] => {
+                let label = format!("{}${}", self.function, label);
+                self.emit(&format!("({})", label));
+            },
+            ["goto", label] => {
+                let label = format!("{}${}", self.function, label);
+                self.emit(&format!("@{}\n0;JMP", label));
+            },
+            ["if-goto", label] => {
+                let label = format!("{}${}", self.function, label);
+                self.pop_d();
+                self.emit(&format!("@{}\nD;JNE", label));
+            },
+            ["call", name, n_args] => self.call(name, number(n_args)?),
+            ["function", name, n_locals] => self.function(name, number(n_locals)?),
+            ["return"] => self.return_(),
+            [command] => self.arithmetic(command)?,
+            _ => return Err(format!("invalid command: {}", line))
+        }
+        Ok(())
+    }
+
+    /// ブートストラップのコードを書く。SPを256にしてSys.initを呼ぶ
+    pub fn bootstrap(&mut self) {
+        self.function = "Sys.init".to_string();
+        self.emit("@256\nD=A\n@SP\nM=D");
+        self.call("Sys.init", 0);
+        self.function.clear();
+    }
+
+    /// vmコードを変換する。これまでに変換したアセンブリに続けて書く
+    pub fn translate(&mut self, vm: &str) -> Result<(), TranslateError> {
+        for (i, line) in vm.lines().enumerate() {
+            self.translate_line(line)
+                .map_err(|message| TranslateError::InvalidLine { line: i + 1, message })?;
+        }
+        Ok(())
+    }
+
+    /// これまでに変換したvmコードで、呼び出しているのに定義していない関数を
+    /// 呼び出した順に返す
+    pub fn undefined_functions(&self) -> Vec<TranslateError> {
+        self.calls.iter()
+            .filter(|name| !self.functions.contains(*name))
+            .map(|name| TranslateError::UndefinedFunction { name: name.clone() })
+            .collect()
+    }
+
+    /// 変換したアセンブリを返す
+    pub fn finish(self) -> String {
+        self.output
+    }
+}
+
+/// vmコードをアセンブリに変換する
+pub fn translate(vm: &str) -> Result<String, TranslateError> {
+    let mut t = VMTranslator::new();
+    t.translate(vm)?;
+    Ok(t.finish())
+}
+
+/// 全てのクラスのvmコードを、ブートストラップのコードから始まる1つのプログラムに
+/// 変換する。エラーの行番号はそのクラスのvmコードの中での行番号。
+/// Sys.initやOSの関数も含め、呼び出す関数がどのクラスにもなければその全てをエラーにする
+pub fn translate_program(classes: &[&str]) -> Result<String, Vec<TranslateError>> {
+    let mut t = VMTranslator::new();
+    t.bootstrap();
+    for vm in classes {
+        t.translate(vm).map_err(|e| vec![e])?;
+    }
+    let errors = t.undefined_functions();
+    if !errors.is_empty() {
+        return Err(errors)
+    }
+    Ok(t.finish())
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::translate;
+    use super::translate_program;
+    use super::TranslateError;
+
+    // テストのためのHackのCPU。アセンブリをそのまま実行する
+    struct Cpu {
+        ram: Vec<i16>,
+        a: i16,
+        d: i16,
+        pc: usize,
+    }
+
+    impl Cpu {
+        // アセンブリをstepsの命令だけ実行する。RAMの初期値はramで与える
+        fn run(asm: &str, ram: &[(usize, i16)], steps: usize) -> Cpu {
+            // ラベルを命令の番号にする
+            let mut labels = HashMap::new();
+            let mut program = Vec::new();
+            for line in asm.lines().map(str::trim).filter(|l| !l.is_empty()) {
+                match line.strip_prefix('(').and_then(|l| l.strip_suffix(')')) {
+                    Some(label) => { labels.insert(label.to_string(), program.len()); },
+                    None => program.push(line),
+                }
+            }
+
+            let mut cpu = Cpu { ram: vec![0; 32768], a: 0, d: 0, pc: 0 };
+            for &(address, value) in ram {
+                cpu.ram[address] = value;
+            }
+            let mut variables = HashMap::new();
+            for _ in 0..steps {
+                let Some(&line) = program.get(cpu.pc) else { break };
+                cpu.pc += 1;
+                if let Some(symbol) = line.strip_prefix('@') {
+                    cpu.a = match symbol {
+                        "SP" => 0, "LCL" => 1, "ARG" => 2, "THIS" => 3, "THAT" => 4,
+                        _ if symbol.starts_with('R') && symbol[1..].parse::<u8>().is_ok() => {
+                            symbol[1..].parse().unwrap()
+                        },
+                        _ => match (symbol.parse(), labels.get(symbol)) {
+                            (Ok(n), _) => n,
+                            (_, Some(&n)) => n as i16,
+                            _ => {
+                                let n = 16 + variables.len() as i16;
+                                *variables.entry(symbol.to_string()).or_insert(n)
+                            },
+                        },
+                    };
+                    continue
+                }
+                cpu.execute(line);
+            }
+            cpu
+        }
+
+        fn execute(&mut self, line: &str) {
+            let (dest, rest) = line.split_once('=').unwrap_or(("", line));
+            let (comp, jump) = rest.split_once(';').unwrap_or((rest, ""));
+            let (a, d, m) = (self.a, self.d, self.ram[self.a as u16 as usize]);
+            let value = match comp {
+                "0" => 0, "1" => 1, "-1" => -1,
+                "A" => a, "D" => d, "M" => m,
+                "-M" => m.wrapping_neg(), "!M" => !m,
+                "A-1" => a.wrapping_sub(1), "M+1" => m.wrapping_add(1), "M-1" => m.wrapping_sub(1),
+                "D+A" => d.wrapping_add(a), "D-A" => d.wrapping_sub(a), "D+M" => d.wrapping_add(m),
+                "M-D" => m.wrapping_sub(d), "D&M" => d & m, "D|M" => d | m,
+                "D+1" => d.wrapping_add(1),
+                _ => panic!("unknown comp: {}", line),
+            };
+            if dest.contains('M') {
+                self.ram[a as u16 as usize] = value;
+            }
+            if dest.contains('D') {
+                self.d = value;
+            }
+            if dest.contains('A') {
+                self.a = value;
+            }
+            let jumped = match jump {
+                "" => false,
+                "JMP" => true,
+                "JEQ" => value == 0,
+                "JNE" => value != 0,
+                "JGT" => value > 0,
+                "JLT" => value < 0,
+                _ => panic!("unknown jump: {}", line),
+            };
+            if jumped {
+                self.pc = self.a as usize;
+            }
+        }
+
+        // スタックの先頭からn個の値を返す
+        fn stack(&self, n: usize) -> Vec<i16> {
+            let sp = self.ram[0] as usize;
+            self.ram[sp - n..sp].to_vec()
+        }
+    }
+
+    #[test]
+    fn test_translate() {
+        let asm = translate("function Main.f 1\npush constant 7\npop static 2\n\
+                             push local 0\nreturn\n").unwrap();
+        assert!(asm.starts_with("(Main.f)\nD=0\n@SP\nAM=M+1\nA=A-1\nM=D\n\
+                                 @7\nD=A\n@SP\nAM=M+1\nA=A-1\nM=D\n\
+                                 @SP\nAM=M-1\nD=M\n@Main.2\nM=D\n\
+                                 @0\nD=A\n@LCL\nA=D+M\nD=M\n"));
+        assert!(asm.ends_with("@R14\nA=M\n0;JMP\n"));
+
+        // ラベルは関数名を付けて区別する
+        let asm = translate("function A.g 0\nlabel L\nif-goto L\ngoto L\neq").unwrap();
+        assert!(asm.contains("(A.g$L)\n@SP\nAM=M-1\nD=M\n@A.g$L\nD;JNE\n@A.g$L\n0;JMP\n"));
+        assert!(asm.contains("D;JEQ\n") && asm.contains("(A.g$TRUE.1)\n"));
+
+        assert_eq!(translate("push constant 1\nfoo"), Err(TranslateError::InvalidLine {
+            line: 2, message: "unknown command: foo".to_string()
+        }));
+        assert!(translate("push temp 8").is_err());
+        assert!(translate("push pointer 2").is_err());
+        assert!(translate("pop constant 0").is_err());
+        assert!(translate("push local x").is_err());
+    }
+
+    #[test]
+    fn test_segments() {
+        // 全てのセグメントにpushとpopをして、書き込んだ場所を確かめる
+        let vm = "function A.f 0\n\
+                  push constant 10\npop local 1\n\
+                  push constant 11\npop argument 2\n\
+                  push constant 12\npop this 3\n\
+                  push constant 13\npop that 4\n\
+                  push constant 14\npop temp 7\n\
+                  push constant 15\npop static 0\n\
+                  push constant 3010\npop pointer 1\n\
+                  push local 1\npush argument 2\npush this 3\npush that 4\n\
+                  push temp 7\npush static 0\npush pointer 0\npush pointer 1\n";
+        let ram = [(0, 256), (1, 300), (2, 400), (3, 3000), (4, 3010)];
+        let cpu = Cpu::run(&translate(vm).unwrap(), &ram, 1000);
+        assert_eq!((cpu.ram[301], cpu.ram[402], cpu.ram[3003], cpu.ram[3014], cpu.ram[12]),
+                   (10, 11, 12, 13, 14));
+        // staticはA.0という変数なので16番地に置かれる
+        assert_eq!(cpu.ram[16], 15);
+        assert_eq!(cpu.ram[4], 3010);
+        assert_eq!(cpu.stack(8), [10, 11, 12, 13, 14, 15, 3000, 3010]);
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let vm = "push constant 7\npush constant 3\nsub\n\
+                  push constant 7\npush constant 3\nadd\n\
+                  push constant 5\nneg\n\
+                  push constant 12\npush constant 10\nand\n\
+                  push constant 12\npush constant 10\nor\n\
+                  push constant 0\nnot\n";
+        let cpu = Cpu::run(&translate(vm).unwrap(), &[(0, 256)], 1000);
+        assert_eq!(cpu.stack(6), [4, 10, -5, 8, 14, -1]);
+    }
+
+    #[test]
+    fn test_comparison() {
+        // 比較は真なら-1、偽なら0になり、ラベルは比較ごとに別になる
+        let vm = "function A.f 0\n\
+                  push constant 1\npush constant 1\neq\n\
+                  push constant 1\npush constant 2\neq\n\
+                  push constant 2\npush constant 1\ngt\n\
+                  push constant 1\npush constant 2\ngt\n\
+                  push constant 1\npush constant 2\nlt\n\
+                  push constant 2\npush constant 1\nlt\n";
+        let asm = translate(vm).unwrap();
+        for label in ["(A.f$TRUE.1)", "(A.f$END.2)", "(A.f$TRUE.11)", "(A.f$END.12)"] {
+            assert_eq!(asm.matches(label).count(), 1, "{}", label);
+        }
+        let cpu = Cpu::run(&asm, &[(0, 256)], 1000);
+        assert_eq!(cpu.stack(6), [-1, 0, -1, 0, -1, 0]);
+    }
+
+    #[test]
+    fn test_call_return() {
+        // Sys.initがA.addを呼び、戻り値をstaticに置いて止まる
+        let program = translate_program(&[
+            "function Sys.init 0\npush constant 3\npush constant 4\ncall A.add 2\n\
+             pop static 0\nlabel END\ngoto END\n",
+            "function A.add 2\npush argument 0\npush argument 1\nadd\npop local 1\n\
+             push local 1\npush local 0\nadd\nreturn\n",
+        ]).unwrap();
+        let cpu = Cpu::run(&program, &[], 1000);
+        assert_eq!(cpu.ram[16], 7);
+
+        // 呼び出しの前に積んだフレームを戻し、引数の位置に戻り値を置く
+        let program = translate_program(&[
+            "function Sys.init 1\npush constant 9\npush constant 5\ncall A.id 1\n\
+             label END\ngoto END\n",
+            "function A.id 0\npush argument 0\nreturn\n",
+        ]).unwrap();
+        let cpu = Cpu::run(&program, &[], 1000);
+        // ブートストラップのフレーム5つとSys.initのローカル1つの後に積む
+        assert_eq!(cpu.ram[0], 256 + 5 + 1 + 2);
+        assert_eq!(cpu.stack(2), [9, 5]);
+        assert_eq!((cpu.ram[1], cpu.ram[2]), (261, 256));
+    }
+
+    #[test]
+    fn test_translate_program() {
+        let asm = translate_program(&["function Main.main 0\npush constant 0\nreturn\n",
+                                      "function Sys.init 0\ncall Main.main 0\n\
+                                       label LOOP\ngoto LOOP\n"]).unwrap();
+        // SPを256にしてからSys.initを呼ぶ
+        assert!(asm.starts_with("@256\nD=A\n@SP\nM=D\n@Sys.init$ret.1\nD=A\n"));
+        assert!(asm.contains("@Sys.init\n0;JMP\n(Sys.init$ret.1)\n(Main.main)\n"));
+        assert!(asm.contains("(Sys.init)\n") && asm.contains("(Sys.init$LOOP)\n"));
+
+        assert_eq!(translate_program(&["return\n", "pop constant 0"]),
+                   Err(vec![TranslateError::InvalidLine {
+            line: 1, message: "invalid segment: constant 0".to_string()
+        }]));
+    }
+
+    #[test]
+    fn test_undefined_function() {
+        // Sys.initやOSの関数がどこにもなければ、呼び出した順に全て報告する
+        let undefined = |name: &str| TranslateError::UndefinedFunction { name: name.to_string() };
+        assert_eq!(translate_program(&["function Main.main 0\npush constant 2\n\
+                                        call Memory.alloc 1\ncall Math.multiply 2\n\
+                                        call Memory.alloc 1\nreturn\n"]),
+                   Err(vec![undefined("Sys.init"), undefined("Memory.alloc"),
+                            undefined("Math.multiply")]));
+        assert!(translate_program(&["function Sys.init 0\ncall Sys.init 0\n"]).is_ok());
+    }
+}
@@ -0,0 +1,184 @@
+//! VMWriterはVMコマンドを出力に書き込む。
+//! 仕様は272page。
+//! 書き込み先にはWriteをimplementしている型ならなんでも使える。
+//! 書き込みのエラーは覚えておき、書き終えた後にfinishで返す
+
+use std::fmt;
+use std::io;
+use std::io::Write;
+
+use super::output::CheckedWriter;
+
+
+/// push/popで使うメモリセグメント
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Segment {
+    Constant,
+    Argument,
+    Local,
+    Static,
+    This,
+    That,
+    Pointer,
+    Temp,
+}
+
+impl fmt::Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Segment::Constant => "constant",
+            Segment::Argument => "argument",
+            Segment::Local => "local",
+            Segment::Static => "static",
+            Segment::This => "this",
+            Segment::That => "that",
+            Segment::Pointer => "pointer",
+            Segment::Temp => "temp",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// 算術コマンド
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Add,
+    Sub,
+    Neg,
+    Eq,
+    Gt,
+    Lt,
+    And,
+    Or,
+    Not,
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Command::Add => "add",
+            Command::Sub => "sub",
+            Command::Neg => "neg",
+            Command::Eq => "eq",
+            Command::Gt => "gt",
+            Command::Lt => "lt",
+            Command::And => "and",
+            Command::Or => "or",
+            Command::Not => "not",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+pub struct VMWriter<W> {
+    output: CheckedWriter<W>,
+}
+
+impl<W: Write> VMWriter<W> {
+    pub fn new(output: W) -> VMWriter<W> {
+        VMWriter { output: CheckedWriter::new(output) }
+    }
+
+    /// pushコマンドを書く
+    pub fn write_push(&mut self, segment: Segment, index: usize) {
+        self.output.write_line(format_args!("push {} {}", segment, index));
+    }
+
+    /// popコマンドを書く
+    pub fn write_pop(&mut self, segment: Segment, index: usize) {
+        self.output.write_line(format_args!("pop {} {}", segment, index));
+    }
+
+    /// 算術コマンドを書く
+    pub fn write_arithmetic(&mut self, command: Command) {
+        self.output.write_line(format_args!("{}", command));
+    }
+
+    /// labelコマンドを書く
+    pub fn write_label(&mut self, label: &str) {
+        self.output.write_line(format_args!("label {}", label));
+    }
+
+    /// gotoコマンドを書く
+    pub fn write_goto(&mut self, label: &str) {
+        self.output.write_line(format_args!("goto {}", label));
+    }
+
+    /// if-gotoコマンドを書く
+    pub fn write_if(&mut self, label: &str) {
+        self.output.write_line(format_args!("if-goto {}", label));
+    }
+
+    /// callコマンドを書く
+    pub fn write_call(&mut self, name: &str, n_args: usize) {
+        self.output.write_line(format_args!("call {} {}", name, n_args));
+    }
+
+    /// functionコマンドを書く
+    pub fn write_function(&mut self, name: &str, n_locals: usize) {
+        self.output.write_line(format_args!("function {} {}", name, n_locals));
+    }
+
+    /// returnコマンドを書く
+    pub fn write_return(&mut self) {
+        self.output.write_line(format_args!("return"));
+    }
+
+    /// 書き込みを終える。途中で書き込めなかったときはそのエラーを返す
+    pub fn finish(self) -> io::Result<()> {
+        self.output.finish()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use std::io::Write;
+
+    use super::VMWriter;
+    use super::Segment;
+    use super::Command;
+
+    #[test]
+    fn test_vm_writer() {
+        let mut output = Vec::new();
+        let mut w = VMWriter::new(&mut output);
+        w.write_function("Main.main", 2);
+        w.write_push(Segment::Constant, 7);
+        w.write_pop(Segment::Local, 1);
+        w.write_arithmetic(Command::Add);
+        w.write_label("L0");
+        w.write_goto("L0");
+        w.write_if("L1");
+        w.write_call("Math.multiply", 2);
+        w.write_return();
+
+        w.finish().unwrap();
+        let s = String::from_utf8(output).unwrap();
+        assert_eq!(s, "function Main.main 2\npush constant 7\npop local 1\n\
+                       add\nlabel L0\ngoto L0\nif-goto L1\n\
+                       call Math.multiply 2\nreturn\n");
+    }
+
+    // 何も書けないWriter
+    struct Broken;
+
+    impl Write for Broken {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("broken pipe"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_vm_writer_error() {
+        let mut w = VMWriter::new(Broken);
+        w.write_push(Segment::Constant, 0);
+        w.write_return();
+        assert_eq!(w.finish().unwrap_err().to_string(), "broken pipe");
+    }
+}
@@ -0,0 +1,422 @@
+//! XmlWriterは構文木をxmlで書き出す。
+//! 出力の形式はnand2tetrisの10章で使うxmlに合わせている。
+//! トークンだけを並べたxml(T.xml)はwrite_tokensで書き出す
+
+use std::io;
+use std::io::{Read, Write};
+
+use super::ast::*;
+use super::tokenizer::Tokenizer;
+use super::tokenizer::token::Token;
+use super::tokenizer::token::Keyword;
+use super::error::CompileError;
+use super::output::CheckedWriter;
+
+
+/// tokenizerから読んだトークンを<tokens>の中に1行ずつ書き出す。
+/// 書き込みのエラーか字句解析のエラーがあればそれを返す
+pub fn write_tokens<R: Read, W: Write>(tokenizer: &mut Tokenizer<R>, output: &mut W)
+    -> Result<(), Vec<CompileError>> {
+    let mut output = CheckedWriter::new(output);
+    output.write_line(format_args!("<tokens>"));
+    for token in tokenizer.by_ref() {
+        output.write_line(format_args!("{}", token.to_xml()));
+    }
+    output.write_line(format_args!("</tokens>"));
+
+    if let Err(e) = output.finish() {
+        return Err(vec![CompileError::io("<output>", &e)])
+    }
+    let errors = tokenizer.take_errors();
+    if !errors.is_empty() {
+        return Err(errors)
+    }
+    Ok(())
+}
+
+
+// 型に対応するトークンを返す
+fn type_token(ty: &Type) -> Token {
+    match ty {
+        Type::Int => Token::Keyword(Keyword::Int),
+        Type::Char => Token::Keyword(Keyword::Char),
+        Type::Boolean => Token::Keyword(Keyword::Boolean),
+        Type::Class(name) => Token::Identifier(name.clone()),
+    }
+}
+
+/// 要素の中は2つの空白で字下げする。中身のない要素も開きタグと閉じタグを
+/// 別の行に書き、公式のxmlと同じ形式にする。書き込みのエラーはfinishで返す
+pub struct XmlWriter<'a, W> {
+    output: CheckedWriter<&'a mut W>,
+    // 字下げの深さ
+    depth: usize,
+}
+
+impl<'a, W: Write> XmlWriter<'a, W> {
+    pub fn new(output: &'a mut W) -> XmlWriter<'a, W> {
+        XmlWriter { output: CheckedWriter::new(output), depth: 0 }
+    }
+
+    /// 書き込みを終える。途中で書き込めなかったときはそのエラーを返す
+    pub fn finish(self) -> io::Result<()> {
+        self.output.finish()
+    }
+
+    // 字下げしてから1行書く
+    fn write_line(&mut self, line: &str) {
+        self.output.write_line(format_args!("{}{}", "  ".repeat(self.depth), line));
+    }
+
+    // 開きタグを書き、中身を1段深く字下げする
+    fn open(&mut self, tag: &str) {
+        self.write_line(&format!("<{}>", tag));
+        self.depth += 1;
+    }
+
+    // 閉じタグを書き、字下げを1段戻す
+    fn close(&mut self, tag: &str) {
+        self.depth -= 1;
+        self.write_line(&format!("</{}>", tag));
+    }
+
+    // トークンを1つ書く
+    fn write_token(&mut self, token: &Token) {
+        self.write_line(&token.to_xml());
+    }
+
+    fn write_keyword(&mut self, keyword: Keyword) {
+        self.write_token(&Token::Keyword(keyword));
+    }
+
+    fn write_symbol(&mut self, c: char) {
+        self.write_token(&Token::Symbol(c));
+    }
+
+    fn write_identifier(&mut self, identifier: &Identifier) {
+        self.write_token(&Token::Identifier(identifier.name.clone()));
+    }
+
+    /// クラスをxmlで書き出す
+    pub fn write_class(&mut self, class: &Class) {
+        self.open("class");
+        self.write_keyword(Keyword::Class);
+        self.write_identifier(&class.name);
+        self.write_symbol('{');
+
+        for class_var_dec in &class.class_var_decs {
+            self.write_class_var_dec(class_var_dec);
+        }
+
+        for subroutine_dec in &class.subroutine_decs {
+            self.write_subroutine_dec(subroutine_dec);
+        }
+
+        self.write_symbol('}');
+        self.close("class");
+    }
+
+    fn write_class_var_dec(&mut self, class_var_dec: &ClassVarDec) {
+        self.open("classVarDec");
+        self.write_keyword(match class_var_dec.kind {
+            ClassVarKind::Static => Keyword::Static,
+            ClassVarKind::Field => Keyword::Field,
+        });
+        self.write_token(&type_token(&class_var_dec.ty));
+        self.write_names(&class_var_dec.names);
+        self.write_symbol(';');
+        self.close("classVarDec");
+    }
+
+    // 変数名をカンマ区切りで書く
+    fn write_names(&mut self, names: &[Identifier]) {
+        for (i, name) in names.iter().enumerate() {
+            if 0 < i {
+                self.write_symbol(',');
+            }
+            self.write_identifier(name);
+        }
+    }
+
+    fn write_subroutine_dec(&mut self, subroutine_dec: &SubroutineDec) {
+        self.open("subroutineDec");
+        self.write_keyword(match subroutine_dec.kind {
+            SubroutineKind::Constructor => Keyword::Constructor,
+            SubroutineKind::Function => Keyword::Function,
+            SubroutineKind::Method => Keyword::Method,
+        });
+        match &subroutine_dec.return_type {
+            Some(ty) => self.write_token(&type_token(ty)),
+            None => self.write_keyword(Keyword::Void),
+        }
+        self.write_identifier(&subroutine_dec.name);
+        self.write_symbol('(');
+
+        self.open("parameterList");
+        for (i, parameter) in subroutine_dec.parameters.iter().enumerate() {
+            if 0 < i {
+                self.write_symbol(',');
+            }
+            self.write_token(&type_token(&parameter.ty));
+            self.write_identifier(&parameter.name);
+        }
+        self.close("parameterList");
+        self.write_symbol(')');
+
+        self.open("subroutineBody");
+        self.write_symbol('{');
+        for var_dec in &subroutine_dec.var_decs {
+            self.open("varDec");
+            self.write_keyword(Keyword::Var);
+            self.write_token(&type_token(&var_dec.ty));
+            self.write_names(&var_dec.names);
+            self.write_symbol(';');
+            self.close("varDec");
+        }
+        self.write_statements(&subroutine_dec.statements);
+        self.write_symbol('}');
+        self.close("subroutineBody");
+        self.close("subroutineDec");
+    }
+
+    fn write_statements(&mut self, statements: &[Statement]) {
+        self.open("statements");
+        for statement in statements {
+            self.write_statement(statement);
+        }
+        self.close("statements");
+    }
+
+    fn write_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Let { name, index, value, .. } => {
+                self.open("letStatement");
+                self.write_keyword(Keyword::Let);
+                self.write_identifier(name);
+                if let Some(index) = index {
+                    self.write_symbol('[');
+                    self.write_expression(index);
+                    self.write_symbol(']');
+                }
+                self.write_symbol('=');
+                self.write_expression(value);
+                self.write_symbol(';');
+                self.close("letStatement");
+            },
+            Statement::If { condition, then_statements, else_statements, .. } => {
+                self.open("ifStatement");
+                self.write_keyword(Keyword::If);
+                self.write_symbol('(');
+                self.write_expression(condition);
+                self.write_symbol(')');
+                self.write_symbol('{');
+                self.write_statements(then_statements);
+                self.write_symbol('}');
+                if let Some(else_statements) = else_statements {
+                    self.write_keyword(Keyword::Else);
+                    self.write_symbol('{');
+                    self.write_statements(else_statements);
+                    self.write_symbol('}');
+                }
+                self.close("ifStatement");
+            },
+            Statement::While { condition, statements, .. } => {
+                self.open("whileStatement");
+                self.write_keyword(Keyword::While);
+                self.write_symbol('(');
+                self.write_expression(condition);
+                self.write_symbol(')');
+                self.write_symbol('{');
+                self.write_statements(statements);
+                self.write_symbol('}');
+                self.close("whileStatement");
+            },
+            Statement::Do { call, .. } => {
+                self.open("doStatement");
+                self.write_keyword(Keyword::Do);
+                self.write_subroutine_call(call);
+                self.write_symbol(';');
+                self.close("doStatement");
+            },
+            Statement::Return { value, .. } => {
+                self.open("returnStatement");
+                self.write_keyword(Keyword::Return);
+                if let Some(value) = value {
+                    self.write_expression(value);
+                }
+                self.write_symbol(';');
+                self.close("returnStatement");
+            },
+        }
+    }
+
+    fn write_expression(&mut self, expression: &Expression) {
+        self.open("expression");
+        self.write_term(&expression.term);
+        for (op, term) in &expression.rest {
+            self.write_symbol(op.symbol());
+            self.write_term(term);
+        }
+        self.close("expression");
+    }
+
+    fn write_term(&mut self, term: &Term) {
+        self.open("term");
+        match term {
+            Term::IntegerConstant { value, .. } => {
+                self.write_token(&Token::Integer(*value));
+            },
+            Term::StringConstant { value, .. } => {
+                self.write_token(&Token::String(value.clone()));
+            },
+            Term::KeywordConstant { value, .. } => {
+                self.write_keyword(match value {
+                    KeywordConstant::True => Keyword::True,
+                    KeywordConstant::False => Keyword::False,
+                    KeywordConstant::Null => Keyword::Null,
+                    KeywordConstant::This => Keyword::This,
+                });
+            },
+            Term::VarName(name) => self.write_identifier(name),
+            Term::ArrayAccess { name, index, .. } => {
+                self.write_identifier(name);
+                self.write_symbol('[');
+                self.write_expression(index);
+                self.write_symbol(']');
+            },
+            Term::SubroutineCall(call) => self.write_subroutine_call(call),
+            Term::Parenthesized { expression, .. } => {
+                self.write_symbol('(');
+                self.write_expression(expression);
+                self.write_symbol(')');
+            },
+            Term::Unary { op, term, .. } => {
+                self.write_symbol(op.symbol());
+                self.write_term(term);
+            },
+        }
+        self.close("term");
+    }
+
+    fn write_subroutine_call(&mut self, call: &SubroutineCall) {
+        if let Some(receiver) = &call.receiver {
+            self.write_identifier(receiver);
+            self.write_symbol('.');
+        }
+        self.write_identifier(&call.name);
+        self.write_symbol('(');
+
+        self.open("expressionList");
+        for (i, argument) in call.arguments.iter().enumerate() {
+            if 0 < i {
+                self.write_symbol(',');
+            }
+            self.write_expression(argument);
+        }
+        self.close("expressionList");
+
+        self.write_symbol(')');
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::XmlWriter;
+    use super::write_tokens;
+    use crate::ast::*;
+    use crate::span::Span;
+    use crate::tokenizer::Tokenizer;
+
+    #[test]
+    fn test_write_tokens() {
+        let mut t = Tokenizer::new(Cursor::new("if (x < 1) { let s = \"a\"; }"));
+        let mut output = Cursor::new(Vec::new());
+        assert_eq!(write_tokens(&mut t, &mut output), Ok(()));
+        let s = String::from_utf8(output.into_inner()).unwrap();
+        assert_eq!(s, "<tokens>\n<keyword> if </keyword>\n<symbol> ( </symbol>\n\
+                       <identifier> x </identifier>\n<symbol> &lt; </symbol>\n\
+                       <integerConstant> 1 </integerConstant>\n<symbol> ) </symbol>\n\
+                       <symbol> { </symbol>\n<keyword> let </keyword>\n\
+                       <identifier> s </identifier>\n<symbol> = </symbol>\n\
+                       <stringConstant> a </stringConstant>\n<symbol> ; </symbol>\n\
+                       <symbol> } </symbol>\n</tokens>\n");
+
+        let mut t = Tokenizer::new(Cursor::new("x \"a"));
+        let mut output = Cursor::new(Vec::new());
+        assert!(write_tokens(&mut t, &mut output).is_err());
+    }
+
+    #[test]
+    fn test_xml_writer_write_class() {
+        let class = Class {
+            name: Identifier { name: "A".to_string(), span: Span::default() },
+            class_var_decs: vec![ClassVarDec {
+                kind: ClassVarKind::Static,
+                ty: Type::Class("B".to_string()),
+                names: vec![
+                    Identifier { name: "b".to_string(), span: Span::default() },
+                    Identifier { name: "c".to_string(), span: Span::default() },
+                ],
+                span: Span::default(),
+            }],
+            subroutine_decs: Vec::new(),
+            span: Span::default(),
+        };
+
+        let mut output = Vec::new();
+        let mut w = XmlWriter::new(&mut output);
+        w.write_class(&class);
+        w.finish().unwrap();
+        let s = String::from_utf8(output).unwrap();
+        assert_eq!(s, "<class>\n  <keyword> class </keyword>\n\
+                       \x20 <identifier> A </identifier>\n  <symbol> { </symbol>\n\
+                       \x20 <classVarDec>\n    <keyword> static </keyword>\n\
+                       \x20   <identifier> B </identifier>\n\
+                       \x20   <identifier> b </identifier>\n    <symbol> , </symbol>\n\
+                       \x20   <identifier> c </identifier>\n    <symbol> ; </symbol>\n\
+                       \x20 </classVarDec>\n  <symbol> } </symbol>\n</class>\n");
+    }
+
+    #[test]
+    fn test_xml_writer_empty_elements() {
+        // 中身のない要素も開きタグと閉じタグを別の行に書く
+        let options = crate::Options { emit: crate::Emit::Xml, ..crate::Options::default() };
+        let output = crate::compile_str("class A { method void f() { do g(); } }", &options)
+            .unwrap();
+        assert_eq!(output.text, "\
+<class>
+  <keyword> class </keyword>
+  <identifier> A </identifier>
+  <symbol> { </symbol>
+  <subroutineDec>
+    <keyword> method </keyword>
+    <keyword> void </keyword>
+    <identifier> f </identifier>
+    <symbol> ( </symbol>
+    <parameterList>
+    </parameterList>
+    <symbol> ) </symbol>
+    <subroutineBody>
+      <symbol> { </symbol>
+      <statements>
+        <doStatement>
+          <keyword> do </keyword>
+          <identifier> g </identifier>
+          <symbol> ( </symbol>
+          <expressionList>
+          </expressionList>
+          <symbol> ) </symbol>
+          <symbol> ; </symbol>
+        </doStatement>
+      </statements>
+      <symbol> } </symbol>
+    </subroutineBody>
+  </subroutineDec>
+  <symbol> } </symbol>
+</class>
+");
+    }
+}
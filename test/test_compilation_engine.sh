@@ -1,17 +1,17 @@
 #!/bin/bash
 # compilation engineをテストするためのshell script
 compiler="../target/debug/compiler"
-comparer="../../../../tools/TextComparer.sh"
 
 
 function test () {
     echo $1": "
-    compile_result=$($compiler $1 out.xml)
-    result=$(sh $comparer $2 out.xml)
-
+    compile_result=$($compiler $1 -o out.xml 2>&1)
+    # 字下げも含めて公式のxmlと一致するかを確かめる。
+    # 公式のxmlは改行が\r\nなので行末の\rは無視する
     printf "\x1b[31m" # 文字を赤色にする
-    if [[ $result == *"Comparison ended successfully"* ]]; then
+    if result=$(diff --strip-trailing-cr $2 out.xml); then
         printf "\x1b[32m" # 文字を黄緑色にする
+        result="Comparison ended successfully"
     fi
 
     if [[ $compile_result != "" ]]; then
@@ -5,7 +5,7 @@ comparer="../../../../tools/TextComparer.sh"
 
 function test () {
     printf $1": "
-    $compiler $1 > out.xml
+    $compiler --emit tokens $1 -o out.xml
     sh $comparer $2 out.xml
     rm out.xml
 }